@@ -1,3 +1,6 @@
+use alloc::vec::Vec;
+use core::fmt;
+
 use bootloader_api::info::FrameBufferInfo;
 use embedded_graphics::{
     geometry::Point,
@@ -61,9 +64,29 @@ pub struct Color {
     pub red: u8,
     pub green: u8,
     pub blue: u8,
+    pub alpha: u8,
 } #[allow(dead_code)] impl Color {
     pub fn new(red: u8, green: u8, blue: u8) -> Self {
-        Self { red, green, blue }
+        Self { red, green, blue, alpha: 255 }
+    }
+
+    /// Creates a color that is blended over the existing pixel using the given alpha value.
+    pub fn new_with_alpha(red: u8, green: u8, blue: u8, alpha: u8) -> Self {
+        Self { red, green, blue, alpha }
+    }
+
+    /// Returns the perceived brightness of this color using the ITU-R BT.601 luma weights.
+    pub fn luminance(self) -> u8 {
+        ((self.red as u32 * 299 + self.green as u32 * 587 + self.blue as u32 * 114) / 1000) as u8
+    }
+
+    /// Returns this color blended over the given destination color.
+    pub fn blend_over(self, destination: Color) -> Color {
+        Color::new(
+            blend_channel(self.red, destination.red, self.alpha),
+            blend_channel(self.green, destination.green, self.alpha),
+            blend_channel(self.blue, destination.blue, self.alpha)
+        )
     }
 } #[allow(dead_code)] impl Into<Rgb888> for Color {
     fn into(self) -> Rgb888 { Rgb888::new(
@@ -73,6 +96,11 @@ pub struct Color {
     ) }
 }
 
+fn blend_channel(source: u8, destination: u8, alpha: u8) -> u8 {
+    let alpha = alpha as u16;
+    ((source as u16 * alpha + destination as u16 * (255 - alpha) + 127) / 255) as u8
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum Colors {
@@ -102,6 +130,106 @@ pub enum Colors {
     } }
 }
 
+/// The 16 EGA colors of the default VGA palette as 6-bit DAC values.
+const VGA_EGA_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (0, 0, 42), (0, 42, 0), (0, 42, 42),
+    (42, 0, 0), (42, 0, 42), (42, 21, 0), (42, 42, 42),
+    (21, 21, 21), (21, 21, 63), (21, 63, 21), (21, 63, 63),
+    (63, 21, 21), (63, 21, 63), (63, 63, 21), (63, 63, 63),
+];
+/// The 16 gray levels of the default VGA palette as 6-bit DAC values.
+const VGA_GRAY_LEVELS: [u8; 16] = [0, 5, 8, 11, 14, 17, 20, 24, 28, 32, 36, 40, 45, 50, 56, 63];
+/// The channel levels (low, three intermediate steps, high) of the nine 24 color hue rings
+/// of the default VGA palette, from high to low intensity and from high to low saturation.
+const VGA_HUE_LEVELS: [[u8; 5]; 9] = [
+    [0, 16, 31, 47, 63], [31, 39, 47, 55, 63], [45, 49, 54, 58, 63],
+    [0, 7, 14, 21, 28], [14, 17, 21, 24, 28], [20, 22, 24, 26, 28],
+    [0, 4, 8, 12, 16], [8, 10, 12, 14, 16], [11, 12, 13, 15, 16],
+];
+
+/// A table of 256 colors used to resolve the indices given to [`DisplayApi::draw_indexed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: [Color; 256],
+} #[allow(dead_code)] impl Palette {
+    pub fn new(colors: [Color; 256]) -> Self {
+        Self { colors }
+    }
+
+    /// Returns the standard VGA 256-color palette: the 16 EGA colors, 16 gray levels,
+    /// nine rings of 24 hues and 8 black entries.
+    pub fn vga() -> Self {
+        let mut colors = [Color::new(0, 0, 0); 256];
+        for (index, (red, green, blue)) in VGA_EGA_COLORS.iter().enumerate() {
+            colors[index] = Color::new(dac_to_8_bit(*red), dac_to_8_bit(*green), dac_to_8_bit(*blue));
+        }
+        for (index, level) in VGA_GRAY_LEVELS.iter().enumerate() {
+            let level = dac_to_8_bit(*level);
+            colors[16 + index] = Color::new(level, level, level);
+        }
+        for (ring, levels) in VGA_HUE_LEVELS.iter().enumerate() {
+            for hue in 0..24 {
+                let (red, green, blue) = hue_ring_levels(hue);
+                colors[32 + ring * 24 + hue] = Color::new(
+                    dac_to_8_bit(levels[red]),
+                    dac_to_8_bit(levels[green]),
+                    dac_to_8_bit(levels[blue])
+                );
+            }
+        }
+        Self { colors }
+    }
+
+    /// Returns the color at the given index.
+    pub fn get(&self, index: u8) -> Color {
+        self.colors[index as usize]
+    }
+
+    /// Replaces the color at the given index.
+    pub fn set(&mut self, index: u8, color: Color) {
+        self.colors[index as usize] = color;
+    }
+
+    /// Resolves each index through this palette.
+    pub fn resolve(&self, indices: &[u8]) -> Vec<Color> {
+        indices.iter().map(|index| self.get(*index)).collect()
+    }
+} impl Default for Palette {
+    fn default() -> Self { Self::vga() }
+}
+
+/// Returns the indices into the level table of a hue ring for the red, green and blue channel of the given hue,
+/// going from blue over magenta, red, yellow, green and cyan back to blue.
+fn hue_ring_levels(hue: usize) -> (usize, usize, usize) {
+    let step = hue % 4;
+    match hue / 4 {
+        0 => (step, 0, 4),
+        1 => (4, 0, 4 - step),
+        2 => (4, step, 0),
+        3 => (4 - step, 4, 0),
+        4 => (0, 4, step),
+        _ => (0, 4 - step, 4),
+    }
+}
+
+/// Converts a 6-bit VGA DAC value to an 8-bit color channel.
+fn dac_to_8_bit(value: u8) -> u8 {
+    (value << 2) | (value >> 4)
+}
+
+/// A user supplied font, e.g. one of the `iso_8859` fonts of embedded-graphics or a font with custom glyphs.
+/// Two custom fonts are equal if they refer to the same font.
+#[derive(Clone, Copy)]
+pub struct CustomFont(pub &'static MonoFont<'static>); impl PartialEq for CustomFont {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self.0, other.0)
+    }
+} impl Eq for CustomFont {} impl fmt::Debug for CustomFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CustomFont({}x{})", self.0.character_size.width, self.0.character_size.height)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum Fonts {
@@ -113,7 +241,14 @@ pub enum Fonts {
     Font9x15, Font9x15B,
     Font9x18, Font9x18B,
     Font10x20,
+    Custom(CustomFont),
 } #[allow(dead_code)] impl Fonts {
+    /// Creates a font from any embedded-graphics mono font.
+    pub fn custom(font: &'static MonoFont<'static>) -> Self {
+        Fonts::Custom(CustomFont(font))
+    }
+
+
     pub fn get_size(self) -> Size { match self {
         Fonts::Font6x9 => Size::new(6, 9),
         Fonts::Font6x10 => Size::new(6, 10),
@@ -134,6 +269,9 @@ pub enum Fonts {
         Fonts::Font9x18 => Size::new(9, 18),
         Fonts::Font9x18B => Size::new(9, 18),
         Fonts::Font10x20 => Size::new(10, 20),
+        Fonts::Custom(CustomFont(font)) => Size::new(
+            font.character_size.width as usize, font.character_size.height as usize
+        ),
     }}
 } #[allow(dead_code)] impl Into<MonoFont<'_>> for Fonts {
     fn into(self) -> MonoFont<'static> { match self {
@@ -156,6 +294,7 @@ pub enum Fonts {
         Fonts::Font9x18 => FONT_9X18,
         Fonts::Font9x18B => FONT_9X18_BOLD,
         Fonts::Font10x20 => FONT_10X20,
+        Fonts::Custom(CustomFont(font)) => *font,
     } }
 } impl Default for Fonts {
     fn default() -> Self { Fonts::Font9x18 }
@@ -191,6 +330,13 @@ pub enum TextLineHeight {
     Half, Full, Double,
     Pixels(u32),
     Percent(u32),
+} #[allow(dead_code)] impl TextLineHeight {
+    /// Returns the distance in pixels between the tops of two consecutive lines of text
+    /// drawn with a font of the given character height.
+    pub fn to_pixels(self, character_height: u32) -> u32 {
+        let line_height: LineHeight = self.into();
+        line_height.to_absolute(character_height)
+    }
 } #[allow(dead_code)] impl Into<LineHeight> for TextLineHeight {
     fn into(self) -> LineHeight { match self {
         TextLineHeight::Half => LineHeight::Percent(50),
@@ -205,24 +351,44 @@ pub trait DisplayApi {
     /// Draws the given buffer to the display without modification.
     fn draw(&mut self, buffer: &[u8]);
     /// Draws a single character to the display at the given position with the given style.
+    /// Each glyph pixel gets drawn as a block of `scale` by `scale` pixels.
     fn draw_char(
         &mut self, character: char, position: Position,
         text_color: Color, background_color: Option<Color>,
         font: MonoFont, underline: bool, strikethrough: bool,
-        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight
+        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
     );
     /// Draws a string to the display at the given position with the given style.
-    /// Does not wrap or scroll the text.
+    /// Does not wrap or scroll the text. Each embedded newline starts a new line at the x position of the first one,
+    /// `line_height.to_pixels(font.character_size.height)` pixels below the previous one.
+    /// Each glyph pixel gets drawn as a block of `scale` by `scale` pixels.
     fn draw_text(
         &mut self, text: &str, position: Position,
         text_color: Color, background_color: Option<Color>,
         font: MonoFont, underline: bool, strikethrough: bool,
-        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight
+        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
     );
+    /// Draws a block of pixels, given row by row, to the display at the given position.
+    /// Parts of the image that lie outside the display are clipped.
+    fn draw_image(&mut self, position: Position, size: Size, pixels: &[Color]);
+    /// Draws a block of palette indices, given row by row, to the display at the given position,
+    /// resolving each index through the installed palette. Parts outside the display are clipped.
+    #[allow(dead_code)]
+    fn draw_indexed(&mut self, position: Position, size: Size, indices: &[u8]);
+    /// Installs the palette used by [`DisplayApi::draw_indexed`].
+    #[allow(dead_code)]
+    fn set_palette(&mut self, palette: Palette);
     /// Overwrites the entire display with the given color.
     fn clear(&mut self, color: Color);
     /// Swaps the front and back buffers, displaying the changes made since the last swap.
     fn swap(&mut self);
+    /// Swaps the front and back buffers completely, regardless of what changed since the last swap.
+    fn swap_all(&mut self);
+    /// Returns whether the display lost what was drawn, e.g. after recovering from a frame buffer that did not match
+    /// its layout, and clears the request. Drivers should redraw everything when this returns true.
+    fn take_redraw_request(&mut self) -> bool;
     /// Returns the information about the frame buffer.
     fn get_info(&self) -> FrameBufferInfo;
+    /// Returns the bytes of the front buffer, i.e. what is currently shown, laid out as described by [`DisplayApi::get_info`].
+    fn snapshot(&self) -> &[u8];
 }
\ No newline at end of file
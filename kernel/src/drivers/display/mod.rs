@@ -1,16 +1,22 @@
 use alloc::rc::Rc;
+use alloc::vec::Vec;
 use core::cell::RefCell;
 
 use crate::api::display::{Color, Colors, DisplayApi, Fonts, Position, Size, TextAlignment, TextBaseline, TextLineHeight};
 use crate::drivers::display::text::{TextDisplayDriver, TextDisplayDriverArgs};
 
+pub mod redraw;
 pub mod text;
 
+/// Holds the driver that draws the base of the screen and a stack of overlay drivers drawn on top of it in order,
+/// e.g. a text HUD with transparent cell backgrounds over graphics.
 pub struct DisplayDriverManager<'a> {
-    pub current_driver: DisplayDriverType<'a>
+    pub current_driver: DisplayDriverType<'a>,
+    overlay_drivers: Vec<DisplayDriverType<'a>>
 } #[allow(dead_code)] impl<'a> DisplayDriverManager<'a> {
     pub fn new() -> Self { Self {
-        current_driver: DisplayDriverType::Unknown
+        current_driver: DisplayDriverType::Unknown,
+        overlay_drivers: Vec::new()
     } }
 
     pub fn set_driver(&mut self, driver: DisplayDriverType<'a>, display: Rc<RefCell<dyn DisplayApi + 'a>>) {
@@ -32,6 +38,60 @@ pub struct DisplayDriverManager<'a> {
         }
     }
 
+    /// Activates a driver on the display and puts it on top of the overlay stack.
+    /// It gets drawn after the current driver and all overlays pushed before it.
+    pub fn push_driver(&mut self, mut driver: DisplayDriverType<'a>, display: Rc<RefCell<dyn DisplayApi + 'a>>) {
+        match &mut driver {
+            DisplayDriverType::Dummy(ref mut driver) => {
+                driver.activate(display);
+            }, DisplayDriverType::Text(ref mut driver, args) => {
+                driver.init(args);
+                driver.activate(display);
+            }, _ => {}
+        }
+        self.overlay_drivers.push(driver);
+    }
+
+    /// Deactivates the topmost overlay driver and returns it. The drivers below redraw everything on the next draw call,
+    /// so nothing of the removed overlay stays on screen.
+    pub fn pop_driver(&mut self) -> Option<DisplayDriverType<'a>> {
+        let mut driver = self.overlay_drivers.pop()?;
+        match &mut driver {
+            DisplayDriverType::Dummy(ref mut driver) => {
+                driver.deactivate();
+            }, DisplayDriverType::Text(ref mut driver, ..) => {
+                driver.deactivate();
+            }, _ => {}
+        }
+
+        for driver in core::iter::once(&mut self.current_driver).chain(self.overlay_drivers.iter_mut()) {
+            if let DisplayDriverType::Text(driver, ..) = driver { driver.init_redraw(); }
+        }
+        Some(driver)
+    }
+
+    /// Returns the overlay drivers from the bottom to the top of the stack.
+    pub fn get_overlay_drivers(&mut self) -> &mut [DisplayDriverType<'a>] {
+        &mut self.overlay_drivers
+    }
+
+    /// Moves the current driver and all overlay drivers over to a new display. The old display is released
+    /// by the drivers and everything gets redrawn on the next draw call.
+    pub fn reactivate(&mut self, display: Rc<RefCell<dyn DisplayApi + 'a>>) {
+        for driver in core::iter::once(&mut self.current_driver).chain(self.overlay_drivers.iter_mut()) {
+            match driver {
+                DisplayDriverType::Dummy(ref mut driver) => {
+                    driver.deactivate();
+                    driver.activate(display.clone());
+                }, DisplayDriverType::Text(ref mut driver, ..) => {
+                    driver.deactivate();
+                    driver.activate(display.clone());
+                    driver.init_redraw();
+                }, _ => {}
+            }
+        }
+    }
+
     pub fn clear(&mut self, color: Color) {
         match &mut self.current_driver {
             DisplayDriverType::Dummy(ref mut driver) => {
@@ -42,13 +102,28 @@ pub struct DisplayDriverManager<'a> {
         }
     }
 
+    /// Draws the current driver and then every overlay driver from the bottom to the top of the stack.
+    /// Drawing a layer may paint over the layers above it, so overlays get redrawn completely on every call.
     pub fn draw_all(&mut self) {
-        match &mut self.current_driver {
-            DisplayDriverType::Dummy(ref mut driver) => {
-                driver.draw_all();
-            }, DisplayDriverType::Text(ref mut driver, ..) => {
-                driver.draw_all();
-            }, _ => {}
+        self.draw_drivers(false);
+    }
+
+    /// Draws all drivers like [`DisplayDriverManager::draw_all`], but text drivers draw everything that is pending
+    /// regardless of their render budget, so nothing is left for later frames.
+    pub fn draw_all_complete(&mut self) {
+        self.draw_drivers(true);
+    }
+
+    fn draw_drivers(&mut self, complete: bool) {
+        for (index, driver) in core::iter::once(&mut self.current_driver).chain(self.overlay_drivers.iter_mut()).enumerate() {
+            match driver {
+                DisplayDriverType::Dummy(ref mut driver) => {
+                    driver.draw_all();
+                }, DisplayDriverType::Text(ref mut driver, ..) => {
+                    if index > 0 { driver.init_redraw(); }
+                    if complete { driver.draw_all_unbudgeted(); } else { driver.draw_all(); }
+                }, _ => {}
+            }
         }
     }
 
@@ -57,6 +132,30 @@ pub struct DisplayDriverManager<'a> {
     }
 }
 
+/// Splits text into lines of at most the given number of columns, breaking at spaces where possible
+/// and at embedded newlines. Words longer than a line get split. Columns are counted in chars,
+/// and every line takes at least one char, so the text always gets consumed.
+pub fn wrap_text(text: &str, columns: usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let columns = columns.max(1);
+
+    for paragraph in text.split('\n') {
+        let mut rest = paragraph;
+        // The byte offset of the char right after the first `columns` chars, if the rest does not fit on one line.
+        while let Some((end, _)) = rest.char_indices().nth(columns) {
+            let split = match rest[..end].rfind(' ') {
+                Some(space) if space > 0 => space,
+                _ => end
+            };
+            lines.push(rest[..split].trim_end());
+            rest = rest[split..].trim_start();
+        }
+        lines.push(rest);
+    }
+
+    lines
+}
+
 #[allow(dead_code)]
 pub enum DisplayDriverType<'a> {
     Unknown,
@@ -91,14 +190,22 @@ pub struct DummyDisplayDriver<'a> {
                 "Kernel Panic -- please reboot your machine! See message below:", Position::new(0, 0),
                 Colors::White.into(), None,
                 Fonts::default().into(), false, false,
-                TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full
-            );
-            display.draw_text(
-                message, Position::new(0, 18),
-                Colors::White.into(), None,
-                Fonts::Font9x18.into(), false, false,
-                TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full
+                TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full, 1
             );
+            let info = display.get_info();
+            let char_size = Fonts::Font9x18.get_size();
+            let max_lines = (info.height / char_size.height).saturating_sub(1);
+
+            let lines = wrap_text(message, info.width / char_size.width);
+            for (index, line) in lines.iter().take(max_lines).enumerate() {
+                let truncated = index + 1 == max_lines && lines.len() > max_lines;
+                display.draw_text(
+                    if truncated { "..." } else { line }, Position::new(0, (index + 1) * char_size.height),
+                    Colors::White.into(), None,
+                    Fonts::Font9x18.into(), false, false,
+                    TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full, 1
+                );
+            }
             display.swap();
         } else { panic!("No display to draw panic message to!"); }
     }
@@ -0,0 +1,57 @@
+use alloc::vec::Vec;
+use crate::api::display::{Position, Region, Size};
+
+/// Collects invalidated rectangles and merges overlapping or adjacent ones,
+/// so every part of the display that changed is only repainted once.
+pub struct RedrawManager {
+    regions: Vec<Region>
+} #[allow(dead_code)] impl RedrawManager {
+    pub fn new() -> Self { Self {
+        regions: Vec::new()
+    } }
+
+    /// Marks a region to be redrawn. Merges it with every region it overlaps or touches.
+    pub fn invalidate(&mut self, region: Region) {
+        if region.size.width == 0 || region.size.height == 0 { return; }
+
+        let mut merged = region;
+        loop {
+            let Some(index) = self.regions.iter().position(|other| touches(merged, *other)) else { break; };
+            merged = union(merged, self.regions.swap_remove(index));
+        }
+
+        self.regions.push(merged);
+    }
+
+    /// Returns whether there is anything to redraw.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Returns all regions to redraw and forgets them.
+    pub fn take_regions(&mut self) -> Vec<Region> {
+        core::mem::take(&mut self.regions)
+    }
+}
+
+/// Returns whether two regions overlap or share an edge. Regions that only share a corner do not touch.
+fn touches(a: Region, b: Region) -> bool {
+    let (a_end_x, a_end_y) = (a.position.x + a.size.width, a.position.y + a.size.height);
+    let (b_end_x, b_end_y) = (b.position.x + b.size.width, b.position.y + b.size.height);
+
+    let x_touch = a.position.x <= b_end_x && b.position.x <= a_end_x;
+    let y_touch = a.position.y <= b_end_y && b.position.y <= a_end_y;
+    let x_edge = a_end_x == b.position.x || b_end_x == a.position.x;
+    let y_edge = a_end_y == b.position.y || b_end_y == a.position.y;
+
+    x_touch && y_touch && !(x_edge && y_edge)
+}
+
+fn union(a: Region, b: Region) -> Region {
+    let start_x = a.position.x.min(b.position.x);
+    let start_y = a.position.y.min(b.position.y);
+    let end_x = (a.position.x + a.size.width).max(b.position.x + b.size.width);
+    let end_y = (a.position.y + a.size.height).max(b.position.y + b.size.height);
+
+    Region::new(Position::new(start_x, start_y), Size::new(end_x - start_x, end_y - start_y))
+}
@@ -1,11 +1,22 @@
 use alloc::borrow::{Cow, ToOwned};
+use alloc::boxed::Box;
 use alloc::rc::Rc;
 use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
+use core::fmt;
 use embedded_graphics::mono_font::MonoFont;
 use crate::api::display::{Color, Colors, DisplayApi, Fonts, Position, Region, Size, TextAlignment, TextBaseline, TextLineHeight};
 use crate::drivers::display::{CommonDisplayDriver, DisplayDriver};
+use crate::drivers::display::redraw::RedrawManager;
+use crate::internal::serial::SerialLoggingLevel;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intensity {
+    Dim, Normal, Bright
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -19,7 +30,7 @@ pub enum TextColor {
     Purple = 5, Fuchsia = 13,
     Teal = 6, Aqua = 14,
     Silver = 7, White = 15
-} impl TextColor {
+} #[allow(dead_code)] impl TextColor {
     #[inline]
     pub fn from_u8(value: u8) -> Option<Self> {
         match value {
@@ -34,6 +45,36 @@ pub enum TextColor {
             _ => None
         }
     }
+
+    /// Returns whether this is one of the eight bright colors.
+    #[inline]
+    pub fn is_bright(self) -> bool {
+        self as u8 >= 8
+    }
+
+    /// Returns the bright variant of this color, e.g. `Red` for `Maroon`.
+    #[inline]
+    pub fn to_bright(self) -> Self {
+        Self::from_u8(self as u8 | 8).unwrap()
+    }
+
+    /// Returns the dim variant of this color, e.g. `Maroon` for `Red`.
+    #[inline]
+    pub fn to_dim(self) -> Self {
+        Self::from_u8(self as u8 & 7).unwrap()
+    }
+
+    /// Returns the RGB value of this color rendered at the given intensity.
+    pub fn to_color(self, intensity: Intensity) -> Color {
+        let color: Color = self.into();
+        let scale = |channel: u8| match intensity {
+            Intensity::Dim => (channel as u16 * 3 / 4) as u8,
+            Intensity::Normal => channel,
+            Intensity::Bright => channel + (255 - channel) / 4
+        };
+
+        Color::new(scale(color.red), scale(color.green), scale(color.blue))
+    }
 } impl Into<Color> for TextColor {
     fn into(self) -> Color {
         match self {
@@ -101,29 +142,39 @@ struct CharacterAttributes(u8); impl CharacterAttributes {
     pub fn strikethrough(&self) -> bool {
         self.0 & 2 != 0
     }
+
+    #[inline]
+    pub fn with_transparent_background(self, transparent_background: bool) -> Self {
+        if transparent_background { Self(self.0 | 4) } else { Self(self.0 & !4) }
+    }
+
+    #[inline]
+    pub fn transparent_background(&self) -> bool {
+        self.0 & 4 != 0
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ScreenChar(u32); impl ScreenChar {
+struct ScreenChar(u64); impl ScreenChar {
     #[inline]
     pub fn new(character: char, color: ColorCode, attributes: CharacterAttributes) -> Self {
-        Self((character as u32) | ((color.0 as u32) << 8) | ((attributes.0 as u32) << 16))
+        Self((character as u64) | ((color.0 as u64) << 32) | ((attributes.0 as u64) << 40))
     }
 
     #[inline]
     pub fn character(&self) -> char {
-        (self.0 & 0xFF) as u8 as char
+        char::from_u32(self.0 as u32).unwrap_or(' ')
     }
 
     #[inline]
     pub fn color(&self) -> ColorCode {
-        ColorCode((self.0 >> 8) as u8)
+        ColorCode((self.0 >> 32) as u8)
     }
 
     #[inline]
     pub fn attributes(&self) -> CharacterAttributes {
-        CharacterAttributes((self.0 >> 16) as u8)
+        CharacterAttributes((self.0 >> 40) as u8)
     }
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -133,17 +184,18 @@ pub struct TextSegment {
     pub text_color: TextColor,
     pub background_color: TextColor,
     pub underline: bool,
-    pub strikethrough: bool
+    pub strikethrough: bool,
+    pub transparent_background: bool
 } impl TextSegment {
     #[inline]
     pub fn new(
         text: impl Into<Cow<'static, str>>, text_position: Position,
         text_color: TextColor, background_color: TextColor,
-        underline: bool, strikethrough: bool
+        underline: bool, strikethrough: bool, transparent_background: bool
     ) -> Self { Self {
         text: text.into(), text_position,
         text_color, background_color,
-        underline, strikethrough
+        underline, strikethrough, transparent_background
     } }
 }
 
@@ -153,9 +205,118 @@ pub enum ScrollDirection {
     Up, Down
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BellMode {
+    None, Visual, Log
+}
+
+/// How ASCII control characters without a special meaning get written.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCharPolicy {
+    /// The character is not written at all.
+    Ignore,
+    /// The character is written in caret notation, e.g. `^A` for `0x01`.
+    Caret,
+    /// The character is written as a single cell with the caret letter in inverted colors.
+    Picture
+}
+
+/// How raw bytes written with [`TextDisplayDriver::write_byte`] map to characters.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodePage {
+    /// Each byte is the Unicode code point of the same value.
+    Latin1,
+    /// The bytes above 0x7F are the box drawing, block and accented characters of the original IBM PC.
+    Cp437
+} #[allow(dead_code)] impl CodePage {
+    /// Returns the character the given byte stands for in this code page.
+    pub fn decode(self, byte: u8) -> char {
+        match self {
+            CodePage::Latin1 => byte as char,
+            CodePage::Cp437 if byte >= 0x80 => CP437_HIGH[(byte - 0x80) as usize],
+            CodePage::Cp437 => byte as char
+        }
+    }
+}
+
+/// The characters of the bytes 0x80 to 0xFF in code page 437.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// How the text cursor gets drawn while it is blinked on.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    /// The whole cell with the character under it in inverted colors.
+    Block,
+    /// The bottom pixel row of the cell.
+    Underline,
+    /// The left pixel column of the cell.
+    Bar,
+    /// The cursor is not drawn at all.
+    Hidden
+}
+
+/// The lines used by [`TextDisplayDriver::draw_box`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxStyle {
+    Single, Double
+} impl BoxStyle {
+    /// Returns the top left, top right, bottom left and bottom right corners
+    /// followed by the horizontal and vertical line.
+    fn glyphs(self) -> [char; 6] {
+        match self {
+            BoxStyle::Single => ['┌', '┐', '└', '┘', '─', '│'],
+            BoxStyle::Double => ['╔', '╗', '╚', '╝', '═', '║']
+        }
+    }
+}
+
 pub const BUFFER_WIDTH: usize = 80;
 pub const BUFFER_HEIGHT: usize = 25;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderMetrics {
+    pub dirty_regions: usize,
+    pub segments: usize,
+    pub draw_text_calls: usize,
+    pub draw_char_calls: usize,
+    pub pixels: usize
+}
+
+/// Everything that decides what the cursor looks like on the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CursorAppearance {
+    position: Position,
+    shape: CursorShape,
+    blink: bool,
+    color: ColorCode,
+    transparent_background: bool
+}
+
+/// Content that is rendered once and then shown behind the dynamic content in its region.
+struct StaticLayer {
+    region: Region,
+    render: fn(&mut TextDisplayDriver<'_>),
+    cells: Option<Vec<ScreenChar>>
+}
+
+/// Renders the graphics behind the text for the given pixel region of the display.
+/// Used by the text driver to composite itself on top of a graphics layer.
+pub type BackgroundLayer = fn(&mut dyn DisplayApi, Position, Size);
+
 pub struct TextDisplayDriverArgs {
     font: Rc<RefCell<Fonts>>,
 } #[allow(dead_code)] impl TextDisplayDriverArgs {
@@ -164,53 +325,308 @@ pub struct TextDisplayDriverArgs {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorState {
+    pub position: Position,
+    pub text_color: TextColor,
+    pub background_color: TextColor,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub transparent_background: bool
+}
+
+/// The cells of a region of the text buffer, captured with [`TextDisplayDriver::capture_region`].
+pub struct RegionSnapshot {
+    region: Region,
+    cells: Vec<ScreenChar>
+}
+
+pub struct TextDisplayDriverState {
+    text_buffer: Box<[ScreenChar; BUFFER_WIDTH * BUFFER_HEIGHT]>,
+    top_row: usize,
+    cursor_state: CursorState,
+    blink: bool
+}
+
 pub struct TextDisplayDriver<'a> {
     display: Option<Rc<RefCell<dyn DisplayApi + 'a>>>,
     font: Option<Fonts>,
     text_buffer: [ScreenChar; BUFFER_WIDTH * BUFFER_HEIGHT],
+    top_row: usize,
+    viewport: Region,
     text_cursor: Position,
     dirty_buffer: [bool; BUFFER_WIDTH * BUFFER_HEIGHT],
     text_color: TextColor,
     background_color: TextColor,
     underline: bool,
     strikethrough: bool,
-    blink: bool
+    transparent_background: bool,
+    blink: bool,
+    bell_mode: BellMode,
+    flash: bool,
+    auto_wrap: bool,
+    cursor_shape: CursorShape,
+    scale: usize,
+    tab_width: usize,
+    reserved_top: usize,
+    reserved_bottom: usize,
+    control_char_policy: ControlCharPolicy,
+    code_page: CodePage,
+    static_layer: Option<StaticLayer>,
+    background_layer: Option<BackgroundLayer>,
+    last_cursor: Option<CursorAppearance>,
+    saved_cursor: Option<CursorState>,
+    render_budget: Option<usize>,
+    render_metrics: RenderMetrics
 } #[allow(dead_code)] impl TextDisplayDriver<'_> {
     /// Initializes the text display driver. Should only get called once by the display driver manager.
     pub fn init(&mut self, args: &mut TextDisplayDriverArgs) {
         self.font = Some(args.font.borrow().to_owned());
     }
 
+    /// Resets the driver to its initial state while keeping the display and font.
+    /// The whole text buffer gets redrawn on the next draw call.
+    pub fn reset(&mut self) {
+        let display = self.display.take();
+        let font = self.font.take();
+
+        *self = Self::new();
+        self.display = display;
+        self.font = font;
+
+        self.init_redraw();
+    }
+
+    /// Sets the part of the screen the driver draws to, in cells of the current font.
+    /// The size is limited to the size of the text buffer. Clears the text buffer, removes all reserved rows
+    /// and moves the cursor to the top left.
+    pub fn set_viewport(&mut self, viewport: Region) {
+        self.viewport = Region::new(viewport.position, Size::new(
+            viewport.size.width.clamp(1, BUFFER_WIDTH),
+            viewport.size.height.clamp(1, BUFFER_HEIGHT)
+        ));
+        self.top_row = 0;
+        self.reserved_top = 0;
+        self.reserved_bottom = 0;
+
+        self.clear_buffer();
+        self.invalidate_static_layer();
+        self.init_redraw();
+    }
+
+    /// Returns the part of the screen the driver draws to, in cells of the current font.
+    #[inline]
+    pub fn get_viewport(&self) -> Region {
+        self.viewport
+    }
+
+    /// Sets the integer factor every glyph gets upscaled by and resizes the viewport to the rows and columns
+    /// that fit on the display at that scale, limited to the size of the text buffer. Clears the text buffer.
+    pub fn set_scale(&mut self, scale: usize) {
+        if scale == 0 { panic!("Scale must be at least 1!"); }
+        self.scale = scale;
+
+        let info = self.display.as_ref().map(|display| display.borrow().get_info());
+        match (info, self.cell_size()) {
+            (Some(info), Some(cell_size)) => self.set_viewport(Region::new(Position::new(0, 0), Size::new(
+                info.width / cell_size.width, info.height / cell_size.height
+            ))), _ => self.init_redraw()
+        }
+    }
+
+    /// Returns the font the driver was initialized with.
+    #[inline]
+    pub fn get_font(&self) -> Option<Fonts> {
+        self.font
+    }
+
+    /// Limits how many cells a single call to `draw_all` redraws, so a large update gets spread over several frames
+    /// instead of stalling one. Dirty regions that do not fit stay dirty and get drawn by the following calls.
+    /// At least one region is drawn per call, even if it is larger than the budget. `None` draws everything at once.
+    /// The frame of a visual bell is always drawn whole.
+    #[inline]
+    pub fn set_render_budget(&mut self, cells: Option<usize>) {
+        self.render_budget = cells;
+    }
+
+    /// Draws all pending changes in a single call, ignoring the render budget.
+    pub fn draw_all_unbudgeted(&mut self) {
+        let render_budget = self.render_budget.take();
+        self.draw_all();
+        self.render_budget = render_budget;
+    }
+
+    /// Returns whether there are cells that still have to be drawn, e.g. because they did not fit into the render budget.
+    pub fn has_pending_redraw(&self) -> bool {
+        self.dirty_buffer.iter().any(|dirty| *dirty)
+    }
+
+    /// Streams what is currently shown on the display over the serial port. Returns false if the driver is not active.
+    pub fn send_screenshot(&self) -> bool {
+        match self.display.as_ref() {
+            Some(display) => {
+                crate::systems::display::send_screenshot(&*display.borrow());
+                true
+            }, None => false
+        }
+    }
+
+    /// Returns what the last call to `draw_all` issued to the display.
+    #[inline]
+    pub fn render_metrics(&self) -> RenderMetrics {
+        self.render_metrics
+    }
+
+    /// Saves the text buffer, the cursor and the current text style so they can be restored later.
+    pub fn save_state(&self) -> TextDisplayDriverState {
+        TextDisplayDriverState {
+            text_buffer: Box::new(self.text_buffer),
+            top_row: self.top_row,
+            cursor_state: self.cursor_state(),
+            blink: self.blink
+        }
+    }
+
+    /// Restores a previously saved state. The whole text buffer gets redrawn on the next draw call.
+    pub fn restore_state(&mut self, state: TextDisplayDriverState) {
+        self.text_buffer = *state.text_buffer;
+        self.top_row = state.top_row;
+        self.set_cursor_state(state.cursor_state);
+        self.blink = state.blink;
+
+        self.init_redraw();
+    }
+
 
     /// Writes a character to the text buffer.
     pub fn write_char(&mut self, character: char) {
         match character {
             '\n' => self.new_line(),
             '\r' => self.move_cursor(Position::new(0, self.text_cursor.y)),
-            '\t' => self.move_cursor(Position::new(self.text_cursor.x + 4, self.text_cursor.y)),
-            _ => {
-                self.write(ScreenChar::new(
-                    character,
-                    ColorCode::new(self.text_color, self.background_color),
-                    CharacterAttributes::new(self.underline, self.strikethrough)
-                ))
-            }
+            '\t' => self.move_cursor(Position::new(self.next_tab_stop(self.text_cursor.x), self.text_cursor.y)),
+            '\x07' => self.bell(),
+            character if character.is_ascii_control() => match self.control_char_policy {
+                ControlCharPolicy::Ignore => {},
+                ControlCharPolicy::Caret => {
+                    self.write_visible_char('^');
+                    self.write_visible_char(caret_letter(character));
+                }, ControlCharPolicy::Picture => {
+                    self.write(ScreenChar::new(
+                        caret_letter(character),
+                        ColorCode::new(self.background_color, self.text_color),
+                        self.current_attributes()
+                    ))
+                }
+            },
+            _ => self.write_visible_char(character)
         }
     }
 
+    /// Writes a raw byte to the text buffer, mapping it to a character through the current code page.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.write_char(self.code_page.decode(byte));
+    }
+
+    /// Writes a character using a classic VGA attribute byte for its colors, without changing the colors for incoming text.
+    /// The low nibble is the foreground and the high nibble the background color. The blink bit selects a
+    /// bright background, like on VGA hardware with blinking disabled.
+    pub fn write_char_with_attr(&mut self, character: char, attribute: u8) {
+        let color = ColorCode(attribute);
+        let (text_color, background_color) = (self.text_color, self.background_color);
+
+        self.text_color = color.foreground();
+        self.background_color = color.background();
+        self.write_char(character);
+
+        self.text_color = text_color;
+        self.background_color = background_color;
+    }
+
     /// Writes a string to the text buffer.
+    ///
+    /// If the string would scroll the whole text buffer out of view, the part that
+    /// would not be visible afterwards is skipped instead of being scrolled line by line.
     pub fn write_string(&mut self, text: &str) {
-        for character in text.chars() {
+        let mut last_row = None;
+        self.simulate_write(text, |_, position| {
+            last_row = Some(position.y);
+            true
+        });
+
+        let scroll = match last_row {
+            Some(row) if row >= self.scroll_end() => row - (self.scroll_end() - 1),
+            _ => 0
+        };
+
+        let mut skip = 0;
+        if scroll >= self.scroll_end() - self.reserved_top {
+            let mut start = None;
+            let top = self.reserved_top;
+            self.simulate_write(text, |index, position| {
+                if position.y < scroll + top { return true; }
+                start = Some((index, Position::new(position.x, position.y - scroll)));
+                false
+            });
+
+            if let Some((index, position)) = start {
+                for row in self.reserved_top..self.scroll_end() {
+                    for col in 0..self.columns() {
+                        self.clear_cell(row, col);
+                    }
+                }
+                self.move_cursor(position);
+                skip = index;
+            }
+        }
+
+        for character in text.chars().skip(skip) {
             self.write_char(character);
         }
     }
 
+    /// Writes a string into the text buffer starting at the given position without moving the cursor or scrolling.
+    /// Text that reaches the right margin is cut off, or continues on the next row at the starting column if `wrap`
+    /// is set. Newlines also continue at the starting column. Text below the last row or outside the viewport is dropped.
+    pub fn write_string_at(&mut self, text: &str, position: Position, wrap: bool) {
+        let mut current = position;
+
+        for character in text.chars() {
+            if current.y >= self.rows() { break; }
+
+            if character == '\n' {
+                current = Position::new(position.x, current.y + 1);
+                continue;
+            } else if character.is_control() { continue; }
+
+            if current.x >= self.columns() {
+                if !wrap { continue; }
+                current = Position::new(position.x, current.y + 1);
+                if current.y >= self.rows() || current.x >= self.columns() { break; }
+            }
+
+            self.write_at(ScreenChar::new(
+                character,
+                ColorCode::new(self.text_color, self.background_color),
+                self.current_attributes()
+            ), current);
+            current.x += 1;
+        }
+    }
+
     /// Writes a string to the text buffer and moves the cursor to the next line.
     pub fn write_line(&mut self, text: &str) {
         self.write_string(text);
         self.new_line();
     }
 
+    /// Moves the cursor to the start of the current line, clears it and writes a string.
+    /// Useful for progress displays that repeatedly redraw the same line.
+    pub fn overwrite_line(&mut self, text: &str) {
+        self.write_char('\r');
+        self.clear_to_end_of_line();
+        self.write_string(text);
+    }
+
     /// Moves the cursor to the next line.
     pub fn new_line(&mut self) {
         self.move_cursor(Position::new(0, self.text_cursor.y + 1));
@@ -241,11 +657,78 @@ pub struct TextDisplayDriver<'a> {
         self.strikethrough = strikethrough;
     }
 
+    /// Sets whether incoming text leaves the background of its cells untouched and only draws the glyph pixels,
+    /// e.g. to overlay text on graphics that were drawn to the display before. The cursor cell is drawn transparent too.
+    /// Transparent cells do not erase what was drawn there before, so this works best together with a background layer.
+    #[inline]
+    pub fn set_transparent_background(&mut self, transparent_background: bool) {
+        self.transparent_background = transparent_background;
+    }
+
+    /// Sets whether text continues on the next line when it reaches the right margin.
+    /// If disabled, text that reaches the right margin keeps overwriting the last cell of the line.
+    #[inline]
+    pub fn set_auto_wrap(&mut self, auto_wrap: bool) {
+        self.auto_wrap = auto_wrap;
+    }
+
+    /// Sets how control characters without a special meaning get written.
+    #[inline]
+    pub fn set_control_char_policy(&mut self, policy: ControlCharPolicy) {
+        self.control_char_policy = policy;
+    }
+
+    /// Sets the code page used to map raw bytes written with [`TextDisplayDriver::write_byte`] to characters.
+    #[inline]
+    pub fn set_code_page(&mut self, code_page: CodePage) {
+        self.code_page = code_page;
+    }
+
+    /// Reserves rows at the top and bottom of the viewport, e.g. for a status bar.
+    /// Scrolling only shifts the rows in between and the cursor is kept out of the reserved rows at the top.
+    pub fn set_reserved_rows(&mut self, top: usize, bottom: usize) {
+        if top + bottom >= self.rows() { panic!("At least one row must not be reserved!"); }
+        self.reserved_top = top;
+        self.reserved_bottom = bottom;
+
+        self.move_cursor(self.text_cursor);
+    }
+
+    /// Writes text into the first reserved row at the top, starting at the given column.
+    /// Uses the current text style, does not move the cursor and cuts off text at the right margin.
+    pub fn write_status(&mut self, text: &str, col: usize) {
+        if self.reserved_top == 0 { panic!("No reserved rows at the top to write the status to!"); }
+
+        for (x, character) in (col..self.columns()).zip(text.chars()) {
+            let character = if character.is_control() { ' ' } else { character };
+            self.write_at(ScreenChar::new(
+                character,
+                ColorCode::new(self.text_color, self.background_color),
+                self.current_attributes()
+            ), Position::new(x, 0));
+        }
+    }
+
+    /// Sets the distance between tab stops. A tab moves the cursor to the next multiple of this width.
+    #[inline]
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        if tab_width == 0 { panic!("Tab width must be at least 1!"); }
+        self.tab_width = tab_width;
+    }
+
 
     /// Moves the cursor to a specific position.
+    /// Rows above the scroll region are clamped to its top. Rows below it are kept, so the next write
+    /// scrolls once for every row the cursor lies past the scroll region, e.g. once per newline.
     #[inline]
     pub fn move_cursor(&mut self, position: Position) {
-        self.text_cursor = position;
+        self.text_cursor = Position::new(position.x, position.y.max(self.reserved_top));
+    }
+
+    /// Sets how the cursor gets drawn.
+    #[inline]
+    pub fn set_cursor_shape(&mut self, cursor_shape: CursorShape) {
+        self.cursor_shape = cursor_shape;
     }
 
     /// Retrieves the current cursor position.
@@ -254,16 +737,73 @@ pub struct TextDisplayDriver<'a> {
         self.text_cursor
     }
 
+    /// Returns the characters of the given visible row, without trailing spaces.
+    pub fn read_row(&self, row: usize) -> String {
+        if row >= self.rows() { return String::new(); }
+
+        let offset = self.row_offset(row);
+        let text: String = self.text_buffer[offset..offset + self.columns()].iter()
+            .map(|screen_char| screen_char.character())
+            .collect();
+        text.trim_end().to_string()
+    }
+
+    /// Retrieves the cursor position together with the attributes used for incoming text.
+    pub fn cursor_state(&self) -> CursorState {
+        CursorState {
+            position: self.text_cursor,
+            text_color: self.text_color,
+            background_color: self.background_color,
+            underline: self.underline,
+            strikethrough: self.strikethrough,
+            transparent_background: self.transparent_background
+        }
+    }
+
+    /// Sets the cursor position and the attributes used for incoming text at once.
+    pub fn set_cursor_state(&mut self, state: CursorState) {
+        self.text_cursor = state.position;
+        self.text_color = state.text_color;
+        self.background_color = state.background_color;
+        self.underline = state.underline;
+        self.strikethrough = state.strikethrough;
+        self.transparent_background = state.transparent_background;
+    }
+
+    /// Saves the cursor position and the attributes used for incoming text, like DECSC.
+    /// Only one state is kept, so saving again overwrites the previously saved state.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = Some(self.cursor_state());
+    }
+
+    /// Restores the state saved by [`TextDisplayDriver::save_cursor`], like DECRC.
+    /// Does nothing if no state was saved. The saved state is kept, so it can be restored again.
+    pub fn restore_cursor(&mut self) {
+        if let Some(state) = self.saved_cursor {
+            self.set_cursor_state(state);
+        }
+    }
+
 
     /// Clears a specific cell in the text buffer.
     pub fn clear_cell(&mut self, row: usize, col: usize) {
-        let index = row * BUFFER_WIDTH + col;
+        let index = self.row_offset(row) + col;
         self.text_buffer[index] = ScreenChar::new(
             ' ',
             ColorCode::new(self.background_color, self.background_color),
             CharacterAttributes::new(false, false),
         );
-        self.dirty_buffer[index] = true;
+        self.dirty_buffer[row * BUFFER_WIDTH + col] = true;
+    }
+
+    /// Clears the current line from the cursor position to the right margin.
+    pub fn clear_to_end_of_line(&mut self) {
+        let Position { x, y } = self.text_cursor;
+        if y >= self.rows() { return; }
+
+        for col in x..self.columns() {
+            self.clear_cell(y, col);
+        }
     }
 
     /// Clears the entire text buffer.
@@ -282,16 +822,11 @@ pub struct TextDisplayDriver<'a> {
         let screen_char = ScreenChar::new(
             character,
             ColorCode::new(self.text_color, self.background_color),
-            CharacterAttributes::new(self.underline, self.strikethrough)
+            self.current_attributes()
         );
 
-        for row in 0..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let index = row * BUFFER_WIDTH + col;
-                self.text_buffer[index] = screen_char;
-                self.dirty_buffer[index] = true;
-            }
-        }
+        self.text_buffer.fill(screen_char);
+        self.init_redraw();
     }
 
     /// Fills a specific region in the text buffer with a specific character.
@@ -299,61 +834,167 @@ pub struct TextDisplayDriver<'a> {
         let screen_char = ScreenChar::new(
             character,
             ColorCode::new(self.text_color, self.background_color),
-            CharacterAttributes::new(self.underline, self.strikethrough)
+            self.current_attributes()
         );
 
         for row in region.position.y..(region.position.y + region.size.height) {
             for col in region.position.x..(region.position.x + region.size.width) {
-                let index = row * BUFFER_WIDTH + col;
+                let index = self.row_offset(row) + col;
                 self.text_buffer[index] = screen_char;
-                self.dirty_buffer[index] = true;
             }
         }
+        self.invalidate_region(region);
+    }
+
+
+    /// Copies the cells of a region so they can be put back with [`TextDisplayDriver::restore_region`],
+    /// e.g. before drawing a dialog over them. The region is clipped to the viewport.
+    pub fn capture_region(&self, region: Region) -> RegionSnapshot {
+        let region = self.clip_region(region);
+        let mut cells = Vec::with_capacity(region.size.width * region.size.height);
+
+        for row in region.position.y..(region.position.y + region.size.height) {
+            let start = self.row_offset(row) + region.position.x;
+            cells.extend_from_slice(&self.text_buffer[start..start + region.size.width]);
+        }
+
+        RegionSnapshot { region, cells }
+    }
+
+    /// Puts back the cells of a captured region and marks them to be redrawn.
+    pub fn restore_region(&mut self, snapshot: &RegionSnapshot) {
+        let region = snapshot.region;
+        if self.clip_region(region) != region { return; }
+
+        for (index, row) in (region.position.y..(region.position.y + region.size.height)).enumerate() {
+            let start = self.row_offset(row) + region.position.x;
+            let cells = &snapshot.cells[index * region.size.width..(index + 1) * region.size.width];
+            self.text_buffer[start..start + region.size.width].copy_from_slice(cells);
+        }
+        self.invalidate_region(region);
+    }
+
+    /// Draws the border of a region with box drawing characters in the current text style.
+    /// Regions smaller than 2x2 cells or not completely inside the viewport are skipped.
+    /// The font needs to contain the box drawing glyphs, e.g. a [`Fonts::Custom`] font.
+    pub fn draw_box(&mut self, region: Region, style: BoxStyle) {
+        let Region { position, size } = region;
+        if size.width < 2 || size.height < 2 { return; }
+        if position.x + size.width > self.columns() || position.y + size.height > self.rows() { return; }
+
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] = style.glyphs();
+        let (right, bottom) = (position.x + size.width - 1, position.y + size.height - 1);
+        let color = ColorCode::new(self.text_color, self.background_color);
+        let attributes = self.current_attributes();
+
+        for x in (position.x + 1)..right {
+            self.write_at(ScreenChar::new(horizontal, color, attributes), Position::new(x, position.y));
+            self.write_at(ScreenChar::new(horizontal, color, attributes), Position::new(x, bottom));
+        }
+        for y in (position.y + 1)..bottom {
+            self.write_at(ScreenChar::new(vertical, color, attributes), Position::new(position.x, y));
+            self.write_at(ScreenChar::new(vertical, color, attributes), Position::new(right, y));
+        }
+
+        self.write_at(ScreenChar::new(top_left, color, attributes), position);
+        self.write_at(ScreenChar::new(top_right, color, attributes), Position::new(right, position.y));
+        self.write_at(ScreenChar::new(bottom_left, color, attributes), Position::new(position.x, bottom));
+        self.write_at(ScreenChar::new(bottom_right, color, attributes), Position::new(right, bottom));
     }
 
 
     /// Scrolls the text buffer by a specific amount of lines in a specific direction.
+    /// If rows are reserved, only the rows between them are scrolled.
     pub fn scroll(&mut self, lines: usize, direction: ScrollDirection) {
         if lines == 0 { return; }
 
-        if lines >= BUFFER_HEIGHT {
+        if self.reserved_top > 0 || self.reserved_bottom > 0 {
+            self.scroll_region(lines, direction);
+            return;
+        }
+
+        if lines >= self.rows() {
             self.clear_buffer();
             return;
         }
 
         match direction {
             ScrollDirection::Up => {
-                for row in 0..(BUFFER_HEIGHT - lines) {
-                    for col in 0..BUFFER_WIDTH {
-                        let from_index = (row + lines) * BUFFER_WIDTH + col;
-                        let to_index = row * BUFFER_WIDTH + col;
-                        self.text_buffer[to_index] = self.text_buffer[from_index];
-                        self.dirty_buffer[to_index] = true;
-                    }
-                }
-                for row in (BUFFER_HEIGHT - lines)..BUFFER_HEIGHT {
-                    for col in 0..BUFFER_WIDTH {
+                self.top_row = (self.top_row + lines) % self.rows();
+                for row in (self.rows() - lines)..self.rows() {
+                    for col in 0..self.columns() {
                         self.clear_cell(row, col);
                     }
                 }
 
                 self.move_cursor(Position::new(self.text_cursor.x, self.text_cursor.y - lines));
             }, ScrollDirection::Down => {
-                for row in (lines..BUFFER_HEIGHT).rev() {
-                    for col in 0..BUFFER_WIDTH {
-                        let from_index = (row - lines) * BUFFER_WIDTH + col;
-                        let to_index = row * BUFFER_WIDTH + col;
-                        self.text_buffer[to_index] = self.text_buffer[from_index];
-                        self.dirty_buffer[to_index] = true;
-                    }
-                }
+                self.top_row = (self.top_row + self.rows() - lines) % self.rows();
                 for row in 0..lines {
-                    for col in 0..BUFFER_WIDTH {
+                    for col in 0..self.columns() {
                         self.clear_cell(row, col);
                     }
                 }
             }
         }
+
+        self.init_redraw();
+    }
+
+    /// Inserts blank lines at the cursor row, like IL. The cursor row and the rows below it move down
+    /// and rows pushed past the bottom margin are lost. The cursor moves to the start of its row.
+    /// Does nothing if the cursor is outside the scroll region.
+    pub fn insert_lines(&mut self, lines: usize) {
+        let (y, end) = (self.text_cursor.y, self.scroll_end());
+        if y < self.reserved_top || y >= end { return; }
+
+        self.shift_rows(y, end, lines.min(end - y), ScrollDirection::Down);
+        self.move_cursor(Position::new(0, y));
+    }
+
+    /// Deletes lines starting at the cursor row, like DL. The rows below move up and blank lines
+    /// are added at the bottom margin. The cursor moves to the start of its row.
+    /// Does nothing if the cursor is outside the scroll region.
+    pub fn delete_lines(&mut self, lines: usize) {
+        let (y, end) = (self.text_cursor.y, self.scroll_end());
+        if y < self.reserved_top || y >= end { return; }
+
+        self.shift_rows(y, end, lines.min(end - y), ScrollDirection::Up);
+        self.move_cursor(Position::new(0, y));
+    }
+
+    /// Inserts blank cells at the cursor, like ICH. The rest of the line moves right
+    /// and cells pushed past the right margin are lost. The cursor does not move.
+    pub fn insert_chars(&mut self, count: usize) {
+        let Position { x, y } = self.text_cursor;
+        let columns = self.columns();
+        if x >= columns || y >= self.rows() { return; }
+
+        let count = count.min(columns - x);
+        let row = self.row_offset(y);
+        self.text_buffer.copy_within(row + x..row + columns - count, row + x + count);
+        for col in x..(x + count) {
+            self.clear_cell(y, col);
+        }
+
+        self.invalidate_region(Region::new(Position::new(x, y), Size::new(columns - x, 1)));
+    }
+
+    /// Deletes cells at the cursor, like DCH. The rest of the line moves left
+    /// and blank cells are added at the right margin. The cursor does not move.
+    pub fn delete_chars(&mut self, count: usize) {
+        let Position { x, y } = self.text_cursor;
+        let columns = self.columns();
+        if x >= columns || y >= self.rows() { return; }
+
+        let count = count.min(columns - x);
+        let row = self.row_offset(y);
+        self.text_buffer.copy_within(row + x + count..row + columns, row + x);
+        for col in (columns - count)..columns {
+            self.clear_cell(y, col);
+        }
+
+        self.invalidate_region(Region::new(Position::new(x, y), Size::new(columns - x, 1)));
     }
 
     /// Toggles the blink attribute for the text cursor.
@@ -361,19 +1002,100 @@ pub struct TextDisplayDriver<'a> {
         self.blink = !self.blink;
     }
 
+    /// Sets what happens when a bell character is written.
+    #[inline]
+    pub fn set_bell_mode(&mut self, bell_mode: BellMode) {
+        self.bell_mode = bell_mode;
+    }
+
+    /// Rings the bell according to the current bell mode.
+    /// A visual bell flashes the whole screen with inverted colors for one draw call.
+    pub fn bell(&mut self) {
+        match self.bell_mode {
+            BellMode::None => {},
+            BellMode::Visual => {
+                self.flash = true;
+                self.init_redraw();
+            }, BellMode::Log => {
+                crate::with_serial_port(|serial_logger| {
+                    serial_logger.log(format_args!("Text display bell."), SerialLoggingLevel::Info);
+                });
+            }
+        }
+    }
+
 
     /// Initializes the whole text buffer to be redrawn on the next draw call.
     pub fn init_redraw(&mut self) {
         self.dirty_buffer.fill(true);
     }
 
+    /// Marks a single cell in the text buffer to be redrawn on the next draw call.
+    #[inline]
+    pub fn invalidate_cell(&mut self, position: Position) {
+        if let (true, true) = self.validate_position(position) {
+            self.dirty_buffer[position.y * BUFFER_WIDTH + position.x] = true;
+        }
+    }
+
+    /// Marks a specific region in the text buffer to be redrawn on the next draw call.
+    /// Parts of the region outside of the text buffer are ignored.
+    pub fn invalidate_region(&mut self, region: Region) {
+        let end_x = (region.position.x + region.size.width).min(self.columns());
+        let end_y = (region.position.y + region.size.height).min(self.rows());
+        let start_x = region.position.x.min(end_x);
+
+        for row in region.position.y..end_y {
+            let row_start = row * BUFFER_WIDTH;
+            self.dirty_buffer[row_start + start_x..row_start + end_x].fill(true);
+        }
+    }
+
+    /// Sets content that only gets rendered once by the given function and is cached afterwards.
+    /// The cached cells are shown in the given region wherever the text buffer contains a blank cell.
+    pub fn set_static_layer(&mut self, region: Region, render: fn(&mut TextDisplayDriver<'_>)) {
+        if let Some(layer) = self.static_layer.take() {
+            self.invalidate_region(layer.region);
+        }
+
+        self.static_layer = Some(StaticLayer { region, render, cells: None });
+        self.invalidate_region(region);
+    }
+
+    /// Removes the static layer. Cells it already composited into the text buffer are kept.
+    pub fn remove_static_layer(&mut self) {
+        if let Some(layer) = self.static_layer.take() {
+            self.invalidate_region(layer.region);
+        }
+    }
+
+    /// Makes the static layer render again on the next draw call.
+    pub fn invalidate_static_layer(&mut self) {
+        let region = match self.static_layer.as_mut() {
+            Some(layer) => {
+                layer.cells = None;
+                layer.region
+            }, None => return
+        };
+
+        self.invalidate_region(region);
+    }
+
+    /// Sets a graphics layer that gets drawn behind the text. While it is set, cell backgrounds are transparent:
+    /// every redrawn cell first gets its part of the background layer drawn and then only the glyph pixels on top.
+    /// The whole text buffer gets redrawn on the next draw call.
+    pub fn set_background_layer(&mut self, background_layer: Option<BackgroundLayer>) {
+        self.background_layer = background_layer;
+        self.init_redraw();
+    }
+
     /// Validates a specific position in the text buffer.
     ///
     /// Returns a tuple with two booleans, the first one indicating if the x position is valid
     /// and the second one indicating if the y position is valid.
     #[inline]
     pub fn validate_position(&mut self, position: Position) -> (bool, bool) {
-        (position.x < BUFFER_WIDTH, position.y < BUFFER_HEIGHT)
+        (position.x < self.columns(), position.y < self.rows())
     }
 
     /// Validates a specific region in the text buffer.
@@ -384,23 +1106,81 @@ pub struct TextDisplayDriver<'a> {
         let end_x = region.position.x + region.size.width;
         let end_y = region.position.y + region.size.height;
 
-        let x_valid_end = end_x < BUFFER_WIDTH;
-        let y_valid_end = end_y < BUFFER_HEIGHT;
+        let x_valid_end = end_x < self.columns();
+        let y_valid_end = end_y < self.rows();
 
         x_valid && y_valid && x_valid_end && y_valid_end
     }
 
 
+    /// Walks through the given text like `write_char` would, without touching the text buffer.
+    /// Calls `visit` with the character index and the unscrolled position of every character that
+    /// would get written, stopping as soon as it returns false.
+    fn simulate_write(&self, text: &str, mut visit: impl FnMut(usize, Position) -> bool) {
+        let mut position = self.text_cursor;
+
+        for (index, character) in text.chars().enumerate() {
+            match character {
+                '\n' => position = Position::new(0, position.y + 1),
+                '\r' => position.x = 0,
+                '\t' => position.x = self.next_tab_stop(position.x),
+                '\x07' => {},
+                _ => {
+                    let cells = match character {
+                        character if character.is_ascii_control() => match self.control_char_policy {
+                            ControlCharPolicy::Ignore => 0,
+                            ControlCharPolicy::Caret => 2,
+                            ControlCharPolicy::Picture => 1
+                        }, _ => 1
+                    };
+
+                    for _ in 0..cells {
+                        if position.x >= self.columns() {
+                            if self.auto_wrap { position = Position::new(0, position.y + 1); }
+                            else { position.x = self.columns() - 1; }
+                        }
+                        if !visit(index, position) { return; }
+                        if self.auto_wrap || position.x + 1 < self.columns() { position.x += 1; }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the attributes used for incoming text.
+    #[inline]
+    fn current_attributes(&self) -> CharacterAttributes {
+        CharacterAttributes::new(self.underline, self.strikethrough)
+            .with_transparent_background(self.transparent_background)
+    }
+
+    #[inline]
+    fn write_visible_char(&mut self, character: char) {
+        self.write(ScreenChar::new(
+            character,
+            ColorCode::new(self.text_color, self.background_color),
+            self.current_attributes()
+        ));
+    }
+
     #[inline]
     fn write(&mut self, character: ScreenChar) {
         let mut new_position = self.text_cursor;
 
         loop {
+            if new_position.y >= self.scroll_end() {
+                self.scroll(1, ScrollDirection::Up);
+                new_position.y -= 1;
+                continue;
+            }
+
             match self.validate_position(new_position) {
                 (true, true) => {
                     self.write_at(character, new_position);
-                    new_position.x += 1;
+                    if self.auto_wrap || new_position.x + 1 < self.columns() { new_position.x += 1; }
                     break;
+                }, (false, true) if !self.auto_wrap => {
+                    new_position.x = self.columns() - 1;
                 }, (false, true) => {
                     new_position.x = 0;
                     new_position.y += 1;
@@ -416,15 +1196,137 @@ pub struct TextDisplayDriver<'a> {
 
     #[inline]
     fn write_at(&mut self, character: ScreenChar, position: Position) {
-        let index = position.y * BUFFER_WIDTH + position.x;
+        let index = self.row_offset(position.y) + position.x;
         self.text_buffer[index] = character;
-        self.dirty_buffer[index] = true;
+        self.dirty_buffer[position.y * BUFFER_WIDTH + position.x] = true;
+    }
+
+    /// Renders the static layer if it has no cached cells yet and copies the cached cells into
+    /// every blank cell of its region.
+    fn composite_static_layer(&mut self) {
+        let Some(mut layer) = self.static_layer.take() else { return; };
+
+        let end_x = (layer.region.position.x + layer.region.size.width).min(self.columns());
+        let end_y = (layer.region.position.y + layer.region.size.height).min(self.rows());
+        let start_x = layer.region.position.x.min(end_x);
+        let start_y = layer.region.position.y.min(end_y);
+
+        if layer.cells.is_none() {
+            let cursor_state = self.cursor_state();
+            (layer.render)(self);
+            self.set_cursor_state(cursor_state);
+
+            let mut cells = Vec::with_capacity((end_x - start_x) * (end_y - start_y));
+            for y in start_y..end_y {
+                for x in start_x..end_x {
+                    cells.push(self.text_buffer[self.row_offset(y) + x]);
+                }
+            }
+            layer.cells = Some(cells);
+        }
+
+        if let Some(cells) = layer.cells.as_ref() {
+            let mut cached = cells.iter();
+            for y in start_y..end_y {
+                for x in start_x..end_x {
+                    let Some(cell) = cached.next() else { break; };
+                    let current = self.text_buffer[self.row_offset(y) + x];
+                    if current.character() == ' ' && current != *cell {
+                        self.write_at(*cell, Position::new(x, y));
+                    }
+                }
+            }
+        }
+
+        self.static_layer = Some(layer);
+    }
+
+    /// Scrolls only the rows between the reserved rows by copying them, as rotating the ring
+    /// of rows would also move the reserved rows.
+    fn scroll_region(&mut self, lines: usize, direction: ScrollDirection) {
+        let (top, end) = (self.reserved_top, self.scroll_end());
+        let lines = lines.min(end - top);
+        self.shift_rows(top, end, lines, direction);
+
+        if direction == ScrollDirection::Up {
+            self.move_cursor(Position::new(self.text_cursor.x, self.text_cursor.y.saturating_sub(lines)));
+        }
+    }
+
+    /// Shifts the rows from `top` up to `end` by the given number of lines, clearing the rows that get vacated.
+    /// Rows outside of that range are not touched. The number of lines must not exceed the number of rows in the range.
+    fn shift_rows(&mut self, top: usize, end: usize, lines: usize, direction: ScrollDirection) {
+        let columns = self.columns();
+
+        match direction {
+            ScrollDirection::Up => {
+                for row in top..(end - lines) {
+                    let (from, to) = (self.row_offset(row + lines), self.row_offset(row));
+                    self.text_buffer.copy_within(from..from + columns, to);
+                }
+                for row in (end - lines)..end {
+                    for col in 0..columns {
+                        self.clear_cell(row, col);
+                    }
+                }
+            }, ScrollDirection::Down => {
+                for row in ((top + lines)..end).rev() {
+                    let (from, to) = (self.row_offset(row - lines), self.row_offset(row));
+                    self.text_buffer.copy_within(from..from + columns, to);
+                }
+                for row in top..(top + lines) {
+                    for col in 0..columns {
+                        self.clear_cell(row, col);
+                    }
+                }
+            }
+        }
+
+        self.invalidate_region(Region::new(Position::new(0, top), Size::new(columns, end - top)));
     }
 
+    /// Returns the row just below the scroll region, i.e. the first reserved row at the bottom.
+    #[inline]
+    fn scroll_end(&self) -> usize {
+        self.rows() - self.reserved_bottom
+    }
+
+    /// Returns the column of the next tab stop after the given column, clamped to the right margin.
+    fn next_tab_stop(&self, x: usize) -> usize {
+        let next = (x / self.tab_width + 1) * self.tab_width;
+        if self.auto_wrap { next.min(self.columns()) } else { next.min(self.columns() - 1) }
+    }
+
+    /// Returns the part of a region that lies inside the viewport.
+    fn clip_region(&self, region: Region) -> Region {
+        let x = region.position.x.min(self.columns());
+        let y = region.position.y.min(self.rows());
+        Region::new(Position::new(x, y), Size::new(
+            region.size.width.min(self.columns() - x),
+            region.size.height.min(self.rows() - y)
+        ))
+    }
+
+    #[inline]
+    fn columns(&self) -> usize {
+        self.viewport.size.width
+    }
+
+    #[inline]
+    fn rows(&self) -> usize {
+        self.viewport.size.height
+    }
 
-    fn get_text_segments(&mut self) -> Vec<TextSegment> {
+    /// Returns the index of the first cell of a row in the text buffer.
+    /// Rows are stored as a ring starting at `top_row`, so scrolling only has to move the top row.
+    #[inline]
+    fn row_offset(&self, row: usize) -> usize {
+        ((self.top_row + row) % self.rows()) * BUFFER_WIDTH
+    }
+
+
+    fn get_text_segments(&mut self, dirty_regions: &[Region]) -> Vec<TextSegment> {
         let mut segments = Vec::new();
-        let dirty_regions = self.get_dirty_regions();
 
         for region in dirty_regions.iter() {
             let start_x = region.position.x;
@@ -438,6 +1340,7 @@ pub struct TextDisplayDriver<'a> {
             let mut current_background_color = self.background_color;
             let mut current_underline = false;
             let mut current_strikethrough = false;
+            let mut current_transparent = false;
             let mut last_x = start_x;
 
             for y in start_y..end_y {
@@ -446,12 +1349,12 @@ pub struct TextDisplayDriver<'a> {
                         segments.push(TextSegment::new(
                             current_text.clone(), current_position,
                             current_text_color, current_background_color,
-                            current_underline, current_strikethrough
+                            current_underline, current_strikethrough, current_transparent
                         ));
                         current_text.clear();
                     }
 
-                    let index = y * BUFFER_WIDTH + x;
+                    let index = self.row_offset(y) + x;
                     let screen_char = self.text_buffer[index];
                     let char_color = screen_char.color();
                     let char_attributes = screen_char.attributes();
@@ -461,15 +1364,16 @@ pub struct TextDisplayDriver<'a> {
                         current_background_color = char_color.background();
                         current_underline = char_attributes.underline();
                         current_strikethrough = char_attributes.strikethrough();
+                        current_transparent = char_attributes.transparent_background();
                         current_text.push(screen_char.character());
                         current_position = Position::new(x, y);
-                    } else if (current_text_color != char_color.foreground() || current_background_color != char_color.background() ||
-                        current_underline != char_attributes.underline() || current_strikethrough != char_attributes.strikethrough()) &&
-                        (current_text_color == TextColor::Black && current_background_color == TextColor::Black) {
+                    } else if current_text_color != char_color.foreground() || current_background_color != char_color.background() ||
+                        current_underline != char_attributes.underline() || current_strikethrough != char_attributes.strikethrough() ||
+                        current_transparent != char_attributes.transparent_background() {
                         segments.push(TextSegment::new(
                             current_text.clone(), current_position,
                             current_text_color, current_background_color,
-                            current_underline, current_strikethrough
+                            current_underline, current_strikethrough, current_transparent
                         ));
 
                         current_text = screen_char.character().to_string();
@@ -478,6 +1382,7 @@ pub struct TextDisplayDriver<'a> {
                         current_background_color = char_color.background();
                         current_underline = char_attributes.underline();
                         current_strikethrough = char_attributes.strikethrough();
+                        current_transparent = char_attributes.transparent_background();
                     } else {
                         current_text.push(screen_char.character());
                     }
@@ -488,7 +1393,7 @@ pub struct TextDisplayDriver<'a> {
                     segments.push(TextSegment::new(
                         current_text.clone(), current_position,
                         current_text_color, current_background_color,
-                        current_underline, current_strikethrough
+                        current_underline, current_strikethrough, current_transparent
                     ));
                     current_text.clear();
                 }
@@ -500,53 +1405,73 @@ pub struct TextDisplayDriver<'a> {
     }
 
     fn get_dirty_regions(&mut self) -> Vec<Region> {
-        let mut regions = Vec::new();
-        let mut visited = [false; BUFFER_WIDTH * BUFFER_HEIGHT];
-
-        for y in 0..BUFFER_HEIGHT {
-            for x in 0..BUFFER_WIDTH {
-                let index = y * BUFFER_WIDTH + x;
-                if self.dirty_buffer[index] && !visited[index] {
-                    let mut bounds = (x, x, y, y);
-                    self.dfs(x, y, &mut visited, &mut bounds);
-
-                    let region = Region::new(
-                        Position::new(bounds.0, bounds.2),
-                        Size::new(bounds.1 - bounds.0 + 1, bounds.3 - bounds.2 + 1),
-                    );
-                    regions.push(region);
-                }
+        let mut redraw_manager = RedrawManager::new();
+
+        for y in 0..self.rows() {
+            let row_start = y * BUFFER_WIDTH;
+            let mut x = 0;
+
+            while x < self.columns() {
+                if !self.dirty_buffer[row_start + x] { x += 1; continue; }
+
+                let start_x = x;
+                while x < self.columns() && self.dirty_buffer[row_start + x] { x += 1; }
+                redraw_manager.invalidate(Region::new(Position::new(start_x, y), Size::new(x - start_x, 1)));
             }
         }
 
-        regions
+        redraw_manager.take_regions()
     }
 
-    fn dfs(&mut self, x: usize, y: usize, visited: &mut [bool; BUFFER_WIDTH * BUFFER_HEIGHT], bounds: &mut (usize, usize, usize, usize)) {
-        let index = y * BUFFER_WIDTH + x;
-        if x >= BUFFER_WIDTH || y >= BUFFER_HEIGHT || visited[index] || !self.dirty_buffer[index] {
-            return;
+
+    /// Returns the pixels covered by the cursor for its current shape, or `None` if it is hidden.
+    fn cursor_rect(&mut self) -> Option<(Position, Size)> {
+        let cell = self.cell_size()?;
+        let position = self.map_position(self.text_cursor);
+
+        match self.cursor_shape {
+            CursorShape::Block => Some((position, cell)),
+            CursorShape::Underline => Some((Position::new(position.x, position.y + cell.height - 1), Size::new(cell.width, 1))),
+            CursorShape::Bar => Some((position, Size::new(1, cell.height))),
+            CursorShape::Hidden => None
         }
+    }
 
-        visited[index] = true;
-        bounds.0 = bounds.0.min(x);
-        bounds.1 = bounds.1.max(x);
-        bounds.2 = bounds.2.min(y);
-        bounds.3 = bounds.3.max(y);
+    /// Returns the cell under the cursor, or `None` if the cursor lies outside the viewport.
+    fn cursor_cell(&self) -> Option<ScreenChar> {
+        let cursor = self.text_cursor;
+        if cursor.x >= self.columns() || cursor.y >= self.rows() { return None; }
+        Some(self.text_buffer[self.row_offset(cursor.y) + cursor.x])
+    }
+
+    fn cursor_appearance(&self) -> CursorAppearance {
+        CursorAppearance {
+            position: self.text_cursor,
+            shape: self.cursor_shape,
+            blink: self.blink,
+            color: ColorCode::new(self.text_color, self.background_color),
+            transparent_background: self.transparent_background
+        }
+    }
 
-        if x > 0 { self.dfs(x - 1, y, visited, bounds); }
-        if x < BUFFER_WIDTH - 1 { self.dfs(x + 1, y, visited, bounds); }
-        if y > 0 { self.dfs(x, y - 1, visited, bounds); }
-        if y < BUFFER_HEIGHT - 1 { self.dfs(x, y + 1, visited, bounds); }
+    /// Returns whether the cell at the given position will be redrawn on the next draw call.
+    fn is_dirty(&self, position: Position) -> bool {
+        position.x < self.columns() && position.y < self.rows() && self.dirty_buffer[position.y * BUFFER_WIDTH + position.x]
     }
 
+    /// Returns the size of a single cell on the display in pixels, including the scale.
+    fn cell_size(&self) -> Option<Size> {
+        let font: MonoFont = self.font?.into();
+        Some(Size::new(
+            font.character_size.width as usize * self.scale,
+            font.character_size.height as usize * self.scale
+        ))
+    }
 
     fn map_position(&mut self, text_position: Position) -> Position {
-        if let Some(font) = self.font.as_ref() {
-            let font: MonoFont = (*font).into();
-
-            let screen_x = text_position.x * font.character_size.width as usize;
-            let screen_y = text_position.y * font.character_size.height as usize;
+        if let Some(cell_size) = self.cell_size() {
+            let screen_x = (self.viewport.position.x + text_position.x) * cell_size.width;
+            let screen_y = (self.viewport.position.y + text_position.y) * cell_size.height;
             return Position::new(screen_x, screen_y);
         }
 
@@ -561,27 +1486,89 @@ pub struct TextDisplayDriver<'a> {
             ColorCode::new(TextColor::Black, TextColor::Black),
             CharacterAttributes::new(false, false)
         ); BUFFER_WIDTH * BUFFER_HEIGHT],
+        top_row: 0,
+        viewport: Region::new(Position::new(0, 0), Size::new(BUFFER_WIDTH, BUFFER_HEIGHT)),
         text_cursor: Position::new(0, 0),
         dirty_buffer: [false; BUFFER_WIDTH * BUFFER_HEIGHT],
         text_color: TextColor::White,
         background_color: TextColor::Black,
         underline: false,
         strikethrough: false,
-        blink: false
+        transparent_background: false,
+        blink: false,
+        bell_mode: BellMode::None,
+        flash: false,
+        auto_wrap: true,
+        cursor_shape: CursorShape::Block,
+        scale: 1,
+        tab_width: 8,
+        reserved_top: 0,
+        reserved_bottom: 0,
+        control_char_policy: ControlCharPolicy::Ignore,
+        code_page: CodePage::Latin1,
+        static_layer: None,
+        background_layer: None,
+        last_cursor: None,
+        saved_cursor: None,
+        render_budget: None,
+        render_metrics: RenderMetrics::default()
     } }
 
     fn draw_all(&mut self) {
-        let segments = self.get_text_segments();
+        let _span = crate::trace_span!("draw_all");
+        self.composite_static_layer();
+
+        let cursor = self.cursor_appearance();
+        if let Some(last_cursor) = self.last_cursor.filter(|last_cursor| last_cursor.position != cursor.position) {
+            self.invalidate_cell(last_cursor.position);
+        }
+        let redraw_cursor = self.flash || self.last_cursor != Some(cursor) || self.is_dirty(cursor.position);
 
-        let pre_calculated_positions: Vec<(Cow<'static, str>, Position, Color, Color, bool, bool)> = segments.iter().map(|segment| {
+        let dirty_regions = self.get_dirty_regions();
+        let dirty_regions = match self.render_budget.filter(|_| !self.flash) {
+            Some(budget) => {
+                let dirty_regions = limit_to_budget(dirty_regions, budget);
+                for region in dirty_regions.iter() {
+                    for row in region.position.y..region.position.y + region.size.height {
+                        let row_start = row * BUFFER_WIDTH + region.position.x;
+                        self.dirty_buffer[row_start..row_start + region.size.width].fill(false);
+                    }
+                }
+                dirty_regions
+            }, None => {
+                self.dirty_buffer.fill(false);
+                dirty_regions
+            }
+        };
+        let mut segments = self.get_text_segments(&dirty_regions);
+        if redraw_cursor && !self.flash && self.blink && self.cursor_shape == CursorShape::Block {
+            // The block cursor draws the whole cell itself, so every pixel of it gets written only once.
+            // This keeps the glyph from flickering through on displays without a back buffer.
+            segments = split_out_cell(segments, self.text_cursor);
+        }
+
+        let mut metrics = RenderMetrics {
+            dirty_regions: dirty_regions.len(),
+            segments: segments.len(),
+            ..RenderMetrics::default()
+        };
+
+        let pre_calculated_positions: Vec<(Cow<'static, str>, Position, Color, Option<Color>, bool, bool)> = segments.iter().map(|segment| {
             let screen_position = self.map_position(segment.text_position);
-            let text_color: Color = segment.text_color.into();
-            let background_color: Color = segment.background_color.into();
+            let (text_color, background_color): (Color, Option<Color>) = if self.flash {
+                (segment.background_color.into(), Some(segment.text_color.into()))
+            } else if segment.transparent_background {
+                (segment.text_color.into(), None)
+            } else { (segment.text_color.into(), Some(segment.background_color.into())) };
             (segment.text.clone(), screen_position, text_color, background_color, segment.underline, segment.strikethrough)
         }).collect();
 
         let cursor_position = self.map_position(self.text_cursor);
+        let cursor_rect = self.cursor_rect();
+        let cursor_cell = self.cursor_cell();
+        let scale = self.scale;
 
+        let mut redraw_requested = false;
         let display_opt = self.display.as_mut();
         let font_opt = self.font.as_ref();
 
@@ -589,34 +1576,93 @@ pub struct TextDisplayDriver<'a> {
             let mut display = display.borrow_mut();
             let font: MonoFont = (*font).into();
 
+            let cell_size = Size::new(font.character_size.width as usize * scale, font.character_size.height as usize * scale);
+            let char_pixels = cell_size.width * cell_size.height;
+            let background_layer = if self.flash { None } else { self.background_layer };
+
             for (text, screen_position, text_color, background_color, underline, strikethrough) in pre_calculated_positions {
+                let background_color = match background_layer {
+                    Some(render) => {
+                        render(&mut *display, screen_position, Size::new(cell_size.width * text.chars().count(), cell_size.height));
+                        None
+                    }, None => background_color
+                };
+
                 display.draw_text(
                     &text, screen_position,
-                    text_color, Some(background_color),
+                    text_color, background_color,
                     font, underline, strikethrough,
-                    TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full
+                    TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full, scale as u32
                 );
+                metrics.draw_text_calls += 1;
+                metrics.pixels += text.chars().count() * char_pixels;
             }
 
-            if self.blink {
-                let color_code = ColorCode::new(self.text_color, self.background_color);
+            match (self.cursor_shape, self.blink) {
+                _ if !redraw_cursor => {},
+                (CursorShape::Hidden, _) => {},
+                (CursorShape::Block, true) => {
+                    // The character under the cursor stays visible, drawn in its own colors inverted.
+                    let (character, color_code, underline, strikethrough) = match cursor_cell {
+                        Some(screen_char) => (
+                            screen_char.character(), screen_char.color(),
+                            screen_char.attributes().underline(), screen_char.attributes().strikethrough()
+                        ), None => (' ', ColorCode::new(self.text_color, self.background_color), false, false)
+                    };
+
+                    display.draw_char(
+                        character, cursor_position,
+                        color_code.invert().foreground().into(), Some(color_code.invert().background().into()),
+                        font, underline, strikethrough,
+                        TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full, scale as u32
+                    );
+                    metrics.draw_char_calls += 1;
+                    metrics.pixels += char_pixels;
+                }, (_, blink) => {
+                    // The cell keeps its character in its own colors. Underline and bar cursors only cover its bottom row
+                    // or left column on top of it, a block cursor in its off phase shows the plain cell.
+                    let (character, color_code, underline, strikethrough, transparent) = match cursor_cell {
+                        Some(screen_char) => (
+                            screen_char.character(), screen_char.color(),
+                            screen_char.attributes().underline(), screen_char.attributes().strikethrough(),
+                            screen_char.attributes().transparent_background()
+                        ), None => (' ', ColorCode::new(self.text_color, self.background_color), false, false, self.transparent_background)
+                    };
+                    let background_color = match background_layer {
+                        Some(render) => {
+                            render(&mut *display, cursor_position, cell_size);
+                            None
+                        }, None if transparent => None,
+                        None => Some(color_code.background().into())
+                    };
+
+                    display.draw_char(
+                        character, cursor_position,
+                        color_code.foreground().into(), background_color,
+                        font, underline, strikethrough,
+                        TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full, scale as u32
+                    );
+                    metrics.draw_char_calls += 1;
+                    metrics.pixels += char_pixels;
 
-                display.draw_char(
-                    ' ', cursor_position,
-                    color_code.invert().foreground().into(), Some(color_code.invert().background().into()),
-                    font, false, false,
-                    TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full
-                );
-            } else {
-                display.draw_char(
-                    ' ', cursor_position,
-                    self.text_color.into(), Some(self.background_color.into()),
-                    font, false, false,
-                    TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full
-                );
+                    if let (true, Some((position, size))) = (blink, cursor_rect) {
+                        let pixels = vec![self.text_color.into(); size.width * size.height];
+                        display.draw_image(position, size, &pixels);
+                        metrics.pixels += pixels.len();
+                    }
+                }
             }
 
             display.swap();
+            redraw_requested = display.take_redraw_request();
+        }
+
+        self.render_metrics = metrics;
+        self.last_cursor = Some(cursor);
+
+        if self.flash || redraw_requested {
+            self.flash = false;
+            self.init_redraw();
         }
     }
 
@@ -642,4 +1688,54 @@ pub struct TextDisplayDriver<'a> {
     fn deactivate(&mut self) {
         self.display = None;
     }
+} impl fmt::Write for TextDisplayDriver<'_> {
+    /// Writes through [`TextDisplayDriver::write_string`], so `write!` handles newlines, tabs and scrolling like any other text.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        TextDisplayDriver::write_char(self, c);
+        Ok(())
+    }
+}
+
+/// Removes a single cell from the segments, splitting the segment covering it in two.
+fn split_out_cell(segments: Vec<TextSegment>, cell: Position) -> Vec<TextSegment> {
+    let mut result = Vec::with_capacity(segments.len() + 1);
+
+    for segment in segments {
+        let start = segment.text_position;
+        let length = segment.text.chars().count();
+        if start.y != cell.y || cell.x < start.x || cell.x >= start.x + length {
+            result.push(segment);
+            continue;
+        }
+
+        let split = cell.x - start.x;
+        let before: String = segment.text.chars().take(split).collect();
+        let after: String = segment.text.chars().skip(split + 1).collect();
+        for (text, x) in [(before, start.x), (after, cell.x + 1)] {
+            if text.is_empty() { continue; }
+            result.push(TextSegment { text: text.into(), text_position: Position::new(x, start.y), ..segment.clone() });
+        }
+    }
+
+    result
+}
+
+/// Returns the leading regions that fit into the given cell budget, but always at least the first one.
+fn limit_to_budget(regions: Vec<Region>, budget: usize) -> Vec<Region> {
+    let mut cells = 0;
+    regions.into_iter().take_while(|region| {
+        let fits = cells == 0 || cells + region.size.width * region.size.height <= budget;
+        cells += region.size.width * region.size.height;
+        fits
+    }).collect()
+}
+
+/// Returns the letter used to show an ASCII control character in caret notation, e.g. `A` for `0x01` and `?` for `0x7F`.
+fn caret_letter(character: char) -> char {
+    ((character as u8) ^ 0x40) as char
 }
\ No newline at end of file
@@ -1,9 +1,10 @@
 use core::alloc::{GlobalAlloc, Layout};
 use core::sync::atomic::{AtomicBool, Ordering};
 use linked_list_allocator::LockedHeap;
+use spin::Mutex;
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
@@ -15,6 +16,18 @@ pub const INITIAL_HEAP_SIZE: usize = 1024 * 1024 * 1; // 1 MiB
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 1024 * 1024 * 32; // 32 MiB
 
+/// Each heap is surrounded by one unmapped page on either side, so running off either end of a heap
+/// page faults instead of corrupting whatever is mapped next to it:
+/// `[start - GUARD_PAGE_SIZE, start)` guard, `[start, start + size)` heap, `[start + size, start + size + GUARD_PAGE_SIZE)` guard.
+pub const GUARD_PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used: usize,
+    pub free: usize,
+    pub size: usize,
+}
+
 struct HeapManager {
     initial_heap: LockedHeap,
     main_heap: LockedHeap,
@@ -37,6 +50,16 @@ struct HeapManager {
     fn init(&self) {
         self.initialized.store(true, Ordering::SeqCst);
     }
+
+    fn stats(&self) -> HeapStats {
+        let heap = if self.initialized.load(Ordering::SeqCst) {
+            self.main_heap.lock()
+        } else {
+            self.initial_heap.lock()
+        };
+
+        HeapStats { used: heap.used(), free: heap.free(), size: heap.size() }
+    }
 } unsafe impl GlobalAlloc for HeapManager {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         if self.initialized.load(Ordering::SeqCst) {
@@ -58,34 +81,109 @@ struct HeapManager {
 #[global_allocator]
 static ALLOCATOR: HeapManager = HeapManager::new();
 
+/// What the page fault handler needs to map pages of a lazily mapped main heap.
+struct LazyHeap {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator
+}
+
+static LAZY_HEAP: Mutex<Option<LazyHeap>> = Mutex::new(None);
+
 pub fn init_initial_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut SimpleBootInfoFrameAllocator,
 ) -> Result<(), MapToError<Size4KiB>> {
-    let result = init_heap_range(mapper, frame_allocator, INITIAL_HEAP_START, INITIAL_HEAP_SIZE);
+    init_heap_range(mapper, frame_allocator, INITIAL_HEAP_START, INITIAL_HEAP_SIZE)?;
     unsafe { ALLOCATOR.init_initial_heap(INITIAL_HEAP_START, INITIAL_HEAP_SIZE); }
-    result
+    Ok(())
 }
 
 pub fn init_main_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut BootInfoFrameAllocator,
 ) -> Result<(), MapToError<Size4KiB>> {
-    let result = init_heap_range(mapper, frame_allocator, HEAP_START, HEAP_SIZE);
+    init_heap_range(mapper, frame_allocator, HEAP_START, HEAP_SIZE)?;
+    unsafe { ALLOCATOR.init_main_heap(HEAP_START, HEAP_SIZE); }
+    Ok(())
+}
+
+/// Reserves the main heap without mapping any of its pages. Each page gets mapped to a fresh frame
+/// by the page fault handler the first time it is touched, see [`map_lazy_heap_page`].
+/// The mapper and frame allocator are kept for that, so they cannot be used by anything else afterwards.
+#[allow(dead_code)]
+pub fn init_main_heap_lazy(
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BootInfoFrameAllocator,
+) -> Result<(), MapToError<Size4KiB>> {
+    check_guard_pages(&mapper, HEAP_START, HEAP_SIZE)?;
+
+    // Initializing the heap writes to its first page, which already has to go through the page fault handler.
+    *LAZY_HEAP.lock() = Some(LazyHeap { mapper, frame_allocator });
     unsafe { ALLOCATOR.init_main_heap(HEAP_START, HEAP_SIZE); }
-    result
+    Ok(())
+}
+
+/// Returns whether the given address lies in the main heap, whose pages may be mapped lazily.
+pub fn is_lazy_heap_address(address: usize) -> bool {
+    (HEAP_START..HEAP_START + HEAP_SIZE).contains(&address)
+}
+
+/// Maps the page containing the given address to a fresh frame if the main heap is mapped lazily and the address lies in it.
+/// Meant to be called by the page fault handler. Returns whether the page got mapped, otherwise the fault is a real one.
+pub fn map_lazy_heap_page(address: usize) -> bool {
+    if !is_lazy_heap_address(address) { return false; }
+
+    let mut lazy_heap = LAZY_HEAP.lock();
+    let Some(LazyHeap { mapper, frame_allocator }) = lazy_heap.as_mut() else { return false; };
+    let Some(frame) = frame_allocator.allocate_frame() else { return false; };
+
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address as u64));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => { flush.flush(); true },
+        Err(_) => false
+    }
 }
 
 pub fn init_allocator() {
     ALLOCATOR.init();
 }
 
+/// Returns whether the main heap is set up and used for allocations.
+/// Before that, code that may run while the heap is missing or broken, like the panic handler, must not allocate.
+pub fn is_initialized() -> bool {
+    ALLOCATOR.initialized.load(Ordering::SeqCst)
+}
+
+/// Returns the start addresses of the guard pages directly below and directly above a heap.
+pub fn guard_pages(start: usize, size: usize) -> (usize, usize) {
+    (start - GUARD_PAGE_SIZE, start + size)
+}
+
+/// Returns which heap the given address is a guard page of, if any.
+pub fn guard_page_heap(address: usize) -> Option<&'static str> {
+    [("initial heap", INITIAL_HEAP_START, INITIAL_HEAP_SIZE), ("main heap", HEAP_START, HEAP_SIZE)].into_iter()
+        .find(|(_, start, size)| {
+            let (below, above) = guard_pages(*start, *size);
+            (below..below + GUARD_PAGE_SIZE).contains(&address) || (above..above + GUARD_PAGE_SIZE).contains(&address)
+        })
+        .map(|(name, _, _)| name)
+}
+
+/// Returns the usage of the heap that is currently used for allocations.
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+/// Maps the pages of a heap. Fails if one of its guard pages is mapped, since it would not catch an overflow then.
 fn init_heap_range(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     start: usize,
     size: usize,
 ) -> Result<(), MapToError<Size4KiB>> {
+    check_guard_pages(mapper, start, size)?;
+
     let initial_page_range = {
         let initial_heap_start = VirtAddr::new(start as u64);
         let initial_heap_end = initial_heap_start + size - 1u64;
@@ -105,5 +203,18 @@ fn init_heap_range(
         };
     }
 
+    Ok(())
+}
+
+/// Fails if one of the guard pages of a heap is mapped, since it would not catch an overflow then.
+fn check_guard_pages(mapper: &impl Mapper<Size4KiB>, start: usize, size: usize) -> Result<(), MapToError<Size4KiB>> {
+    let (below, above) = guard_pages(start, size);
+    for guard_page in [below, above] {
+        let page = Page::containing_address(VirtAddr::new(guard_page as u64));
+        if let Ok(frame) = mapper.translate_page(page) {
+            return Err(MapToError::PageAlreadyMapped(frame));
+        }
+    }
+
     Ok(())
 }
\ No newline at end of file
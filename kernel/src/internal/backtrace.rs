@@ -0,0 +1,63 @@
+use core::arch::asm;
+use core::ops::Range;
+use x86_64::instructions::read_rip;
+use crate::internal::serial::{SerialLoggingLevel, SerialPortLogger};
+
+/// Maximum number of frames logged in a backtrace.
+const MAX_FRAMES: usize = 16;
+/// How far above the current stack pointer saved frame pointers are followed. Matches the kernel stack size.
+const STACK_WINDOW: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64
+}
+
+/// Reads the instruction, stack and frame pointer of the caller.
+#[inline(always)]
+pub fn read_registers() -> Registers {
+    let (rsp, rbp): (u64, u64);
+    unsafe {
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+
+    Registers { rip: read_rip().as_u64(), rsp, rbp }
+}
+
+/// Follows the chain of saved frame pointers starting at `rbp` and calls `visit` with the depth and return address of each frame.
+/// `read` reads the 8 bytes at an address. The walk stops at the first frame pointer that is misaligned,
+/// outside of `stack` or not above the previous one, so it never reads outside of `stack`.
+pub fn walk_frame_pointers(rbp: u64, stack: Range<u64>, read: impl Fn(u64) -> u64, mut visit: impl FnMut(usize, u64)) {
+    let mut frame = rbp;
+
+    for depth in 0..MAX_FRAMES {
+        if frame % 8 != 0 || !stack.contains(&frame) || !stack.contains(&(frame + 8)) { break; }
+
+        let return_address = read(frame + 8);
+        if return_address == 0 { break; }
+        visit(depth, return_address);
+
+        let next = read(frame);
+        if next <= frame { break; }
+        frame = next;
+    }
+}
+
+/// Logs the current registers and a backtrace at panic level without allocating.
+/// The backtrace is only complete if the kernel is built with frame pointers.
+pub fn log_snapshot(serial_port: &mut SerialPortLogger) {
+    let registers = read_registers();
+    serial_port.log(format_args!("rip: {:#018x}, rsp: {:#018x}, rbp: {:#018x}",
+        registers.rip, registers.rsp, registers.rbp
+    ), SerialLoggingLevel::Panic);
+
+    let stack = registers.rsp..registers.rsp.saturating_add(STACK_WINDOW);
+    walk_frame_pointers(registers.rbp, stack, |address| unsafe {
+        (address as *const u64).read_volatile()
+    }, |depth, return_address| {
+        serial_port.log(format_args!("  #{}: {:#018x}", depth, return_address), SerialLoggingLevel::Panic);
+    });
+}
@@ -0,0 +1,31 @@
+use alloc::string::String;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Appends the standard base64 encoding of the bytes to the output, padded with `=`.
+pub fn encode_into(input: &[u8], output: &mut String) {
+    for chunk in input.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(ALPHABET[(bits >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else { output.push('='); }
+        }
+    }
+}
+
+/// Encodes the bytes in lines of at most `line_bytes` input bytes each and hands every line to the given function.
+/// The line length gets rounded down to a multiple of three, so only the last line can contain padding
+/// and the lines can simply be concatenated to decode them.
+pub fn encode_chunked(input: &[u8], line_bytes: usize, mut f: impl FnMut(&str)) {
+    let line_bytes = (line_bytes / 3).max(1) * 3;
+    let mut line = String::with_capacity(line_bytes / 3 * 4);
+
+    for chunk in input.chunks(line_bytes) {
+        line.clear();
+        encode_into(chunk, &mut line);
+        f(&line);
+    }
+}
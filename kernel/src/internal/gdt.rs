@@ -1,4 +1,5 @@
 use core::ptr::addr_of;
+use core::sync::atomic::{AtomicBool, Ordering};
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
@@ -13,7 +14,13 @@ struct Selectors {
     }
 }
 
+/// Index into the interrupt stack table of the TSS used by the double fault handler.
+/// The handler runs on its own stack, so a double fault caused by a kernel stack overflow
+/// does not immediately turn into a triple fault.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const _: () = assert!((DOUBLE_FAULT_IST_INDEX as usize) < 7, "The interrupt stack table only has 7 entries!");
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
@@ -37,10 +44,14 @@ lazy_static! {
     };
 }
 
+/// Loads the GDT, reloads the code segment and loads the TSS containing the double fault stack.
+/// Has to be called before the IDT gets loaded. Calling it again does nothing.
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, Segment};
 
+    if INITIALIZED.swap(true, Ordering::SeqCst) { return; }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
@@ -1,16 +1,34 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin::Mutex;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
-use crate::internal::serial::SerialLoggingLevel;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use crate::internal::{allocator, latency};
+use crate::internal::serial::{self, SerialLoggingLevel};
+use crate::internal::timer;
 
 const PIC_1_OFFSET: u8 = 32;
 const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+const PIC_1_COMMAND_PORT: u16 = 0x20;
+const PIC_2_COMMAND_PORT: u16 = 0xA0;
+const PIC_1_DATA_PORT: u16 = 0x21;
+const PIC_2_DATA_PORT: u16 = 0xA1;
+
+/// OCW3 command making the next read of the command port return the in-service register.
+const PIC_READ_ISR: u8 = 0x0B;
+const PIC_END_OF_INTERRUPT: u8 = 0x20;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
+    Serial = PIC_1_OFFSET + 4,
+    SpuriousMaster = PIC_1_OFFSET + 7,
+    SpuriousSlave = PIC_2_OFFSET + 7,
 } impl InterruptIndex {
     fn as_u8(self) -> u8 {
         self as u8
@@ -21,6 +39,30 @@ pub enum InterruptIndex {
     }
 }
 
+/// How often each handler was invoked since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InterruptStats {
+    pub timer: u64,
+    pub serial: u64,
+    pub breakpoint: u64,
+    pub invalid_opcode: u64,
+    pub general_protection_fault: u64,
+    pub page_fault: u64,
+    pub double_fault: u64,
+    pub spurious: u64
+}
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+static TIMER_COUNT: AtomicU64 = AtomicU64::new(0);
+static SERIAL_COUNT: AtomicU64 = AtomicU64::new(0);
+static BREAKPOINT_COUNT: AtomicU64 = AtomicU64::new(0);
+static INVALID_OPCODE_COUNT: AtomicU64 = AtomicU64::new(0);
+static GENERAL_PROTECTION_FAULT_COUNT: AtomicU64 = AtomicU64::new(0);
+static PAGE_FAULT_COUNT: AtomicU64 = AtomicU64::new(0);
+static DOUBLE_FAULT_COUNT: AtomicU64 = AtomicU64::new(0);
+static SPURIOUS_COUNT: AtomicU64 = AtomicU64::new(0);
+
 static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
@@ -29,6 +71,9 @@ lazy_static! {
         let mut idt = InterruptDescriptorTable::new();
 
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
         unsafe {
             idt.double_fault.set_handler_fn(double_fault_handler)
                 .set_stack_index(super::gdt::DOUBLE_FAULT_IST_INDEX);
@@ -36,48 +81,192 @@ lazy_static! {
 
         idt[InterruptIndex::Timer.as_usize()]
             .set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Serial.as_usize()]
+            .set_handler_fn(serial_interrupt_handler);
+        idt[InterruptIndex::SpuriousMaster.as_usize()]
+            .set_handler_fn(spurious_master_interrupt_handler);
+        idt[InterruptIndex::SpuriousSlave.as_usize()]
+            .set_handler_fn(spurious_slave_interrupt_handler);
 
         idt
     };
 }
 
+/// Loads the IDT, initializes the PICs and enables interrupts. Calling it again does nothing.
 pub fn init() {
+    if INITIALIZED.swap(true, Ordering::SeqCst) { return; }
+
     IDT.load();
     unsafe { PICS.lock().initialize() };
     x86_64::instructions::interrupts::enable();
 }
 
+/// Masks an IRQ line (0 to 15) so the PICs stop delivering its interrupts.
+/// Lines 8 to 15 belong to the slave PIC.
+#[allow(dead_code)]
+pub fn mask_irq(line: u8) {
+    let (data_port, bit) = mask_bit(line);
+    without_interrupts(|| unsafe {
+        let mut port = Port::<u8>::new(data_port);
+        let mask = port.read();
+        port.write(mask | bit);
+    });
+}
+
+/// Unmasks an IRQ line (0 to 15) that was masked before. Lines 8 to 15 belong to the slave PIC.
+#[allow(dead_code)]
+pub fn unmask_irq(line: u8) {
+    let (data_port, bit) = mask_bit(line);
+    without_interrupts(|| unsafe {
+        let mut port = Port::<u8>::new(data_port);
+        let mask = port.read();
+        port.write(mask & !bit);
+    });
+}
+
+/// Returns the data port of the PIC an IRQ line belongs to and the bit of the line in its mask.
+fn mask_bit(line: u8) -> (u16, u8) {
+    match line {
+        0..8 => (PIC_1_DATA_PORT, 1 << line),
+        8..16 => (PIC_2_DATA_PORT, 1 << (line - 8)),
+        _ => panic!("Invalid IRQ line {}!", line)
+    }
+}
+
+/// Returns how often each interrupt handler was invoked since boot.
+pub fn interrupt_stats() -> InterruptStats {
+    InterruptStats {
+        timer: TIMER_COUNT.load(Ordering::Relaxed),
+        serial: SERIAL_COUNT.load(Ordering::Relaxed),
+        breakpoint: BREAKPOINT_COUNT.load(Ordering::Relaxed),
+        invalid_opcode: INVALID_OPCODE_COUNT.load(Ordering::Relaxed),
+        general_protection_fault: GENERAL_PROTECTION_FAULT_COUNT.load(Ordering::Relaxed),
+        page_fault: PAGE_FAULT_COUNT.load(Ordering::Relaxed),
+        double_fault: DOUBLE_FAULT_COUNT.load(Ordering::Relaxed),
+        spurious: SPURIOUS_COUNT.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns whether an interrupt on the lowest priority line (IRQ 7 or 15) was spurious, given the
+/// in-service register of the PIC that raised it. A real interrupt has its bit set while being serviced.
+#[inline]
+fn is_spurious(in_service: u8) -> bool {
+    in_service & 0x80 == 0
+}
+
+/// Reads the in-service register of the PIC with the given command port.
+unsafe fn read_in_service(command_port: u16) -> u8 {
+    let mut port = Port::<u8>::new(command_port);
+    port.write(PIC_READ_ISR);
+    port.read()
+}
+
+#[inline]
+fn count(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
 extern "x86-interrupt" fn breakpoint_handler(
     stack_frame: InterruptStackFrame
 ) {
-    if let Some(serial_logger) = crate::get_serial_port() {
-        serial_logger.log(
-            format_args!("BREAKPOINT EXCEPTION:\n{:#?}", stack_frame),
-            SerialLoggingLevel::Info
-        );
-    }
+    count(&BREAKPOINT_COUNT);
+    crate::with_serial_port(|serial_logger| serial_logger.log(
+        format_args!("BREAKPOINT EXCEPTION:\n{:#?}", stack_frame),
+        SerialLoggingLevel::Info
+    ));
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64
 ) -> ! {
-    if let Some(serial_logger) = crate::get_serial_port() {
-        serial_logger.log(
-            format_args!("DOUBLE FAULT EXCEPTION:\n{:#?}", stack_frame),
-            SerialLoggingLevel::Error
-        );
-    }
+    count(&DOUBLE_FAULT_COUNT);
+    crate::try_with_serial_port(|serial_logger| serial_logger.log(
+        format_args!("DOUBLE FAULT EXCEPTION:\n{:#?}", stack_frame),
+        SerialLoggingLevel::Error
+    ));
     panic!("DOUBLE FAULT EXCEPTION!");
 }
 
+extern "x86-interrupt" fn invalid_opcode_handler(
+    stack_frame: InterruptStackFrame
+) {
+    count(&INVALID_OPCODE_COUNT);
+    crate::try_with_serial_port(|serial_logger| serial_logger.log(
+        format_args!("INVALID OPCODE EXCEPTION:\n{:#?}", stack_frame),
+        SerialLoggingLevel::Error
+    ));
+    panic!("INVALID OPCODE EXCEPTION at {:#x}!", stack_frame.instruction_pointer.as_u64());
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame, error_code: u64
+) {
+    count(&GENERAL_PROTECTION_FAULT_COUNT);
+    crate::try_with_serial_port(|serial_logger| serial_logger.log(
+        format_args!("GENERAL PROTECTION FAULT (selector error code {:#x}):\n{:#?}", error_code, stack_frame),
+        SerialLoggingLevel::Error
+    ));
+    panic!("GENERAL PROTECTION FAULT at {:#x} with error code {:#x}!", stack_frame.instruction_pointer.as_u64(), error_code);
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode
+) {
+    count(&PAGE_FAULT_COUNT);
+    let address = Cr2::read();
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) && allocator::map_lazy_heap_page(address.as_u64() as usize) {
+        return;
+    }
+
+    crate::try_with_serial_port(|serial_logger| serial_logger.log(
+        format_args!("PAGE FAULT accessing {:#x} ({:?}):\n{:#?}", address.as_u64(), error_code, stack_frame),
+        SerialLoggingLevel::Error
+    ));
+
+    match allocator::guard_page_heap(address.as_u64() as usize) {
+        Some(heap) => panic!("HEAP OVERFLOW into a guard page of the {} at {:#x}!", heap, address.as_u64()),
+        None => panic!("PAGE FAULT at {:#x} accessing {:#x}!", stack_frame.instruction_pointer.as_u64(), address.as_u64())
+    }
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(
     _stack_frame: InterruptStackFrame
+) { latency::measure_handler(|| unsafe {
+    count(&TIMER_COUNT);
+    timer::tick();
+    PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+}) }
+
+extern "x86-interrupt" fn serial_interrupt_handler(
+    _stack_frame: InterruptStackFrame
+) { latency::measure_handler(|| unsafe {
+    count(&SERIAL_COUNT);
+    crate::with_serial_port(|serial_logger| {
+        while let Some(byte) = serial_logger.try_read_byte() {
+            serial::push_input_byte(byte);
+        }
+    });
+    PICS.lock().notify_end_of_interrupt(InterruptIndex::Serial.as_u8());
+}) }
+
+extern "x86-interrupt" fn spurious_master_interrupt_handler(
+    _stack_frame: InterruptStackFrame
 ) { unsafe {
-    if let Some(serial_logger) = crate::get_serial_port() {
-        serial_logger.log(
-            format_args!("TIMER INTERRUPT"),
-            SerialLoggingLevel::Info
-        );
+    if is_spurious(read_in_service(PIC_1_COMMAND_PORT)) {
+        count(&SPURIOUS_COUNT);
+        return;
     }
-    PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    PICS.lock().notify_end_of_interrupt(InterruptIndex::SpuriousMaster.as_u8());
+} }
+
+extern "x86-interrupt" fn spurious_slave_interrupt_handler(
+    _stack_frame: InterruptStackFrame
+) { unsafe {
+    if is_spurious(read_in_service(PIC_2_COMMAND_PORT)) {
+        count(&SPURIOUS_COUNT);
+        // The master did see a real interrupt on its cascade line, so only it gets an end of interrupt.
+        Port::<u8>::new(PIC_1_COMMAND_PORT).write(PIC_END_OF_INTERRUPT);
+        return;
+    }
+    PICS.lock().notify_end_of_interrupt(InterruptIndex::SpuriousSlave.as_u8());
 } }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use crate::internal::serial::SerialLoggingLevel;
+
+const KEYBOARD_DATA_PORT: u16 = 0x60;
+const KEYBOARD_CONTROLLER_STATUS_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_OUTPUT_FULL: u8 = 0x01;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 0x02;
+
+const KEYBOARD_SET_LEDS: u8 = 0xED;
+const KEYBOARD_ACK: u8 = 0xFA;
+const KEYBOARD_RESEND: u8 = 0xFE;
+
+/// How often a byte gets sent again when the keyboard asks for it.
+const MAX_RETRIES: usize = 3;
+/// How often the status register gets polled before giving up on the keyboard controller.
+const MAX_POLLS: usize = 100_000;
+
+/// The lock keys that have an LED on the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockState {
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool
+} impl LockState {
+    /// Returns the LED bitmask the keyboard expects after the set LEDs command.
+    pub fn led_mask(self) -> u8 {
+        let mut mask = 0;
+        if self.scroll_lock { mask |= 0x01; }
+        if self.num_lock { mask |= 0x02; }
+        if self.caps_lock { mask |= 0x04; }
+        mask
+    }
+}
+
+/// Returns the bytes that set the keyboard LEDs to the given lock state.
+pub fn led_command(state: LockState) -> [u8; 2] {
+    [KEYBOARD_SET_LEDS, state.led_mask()]
+}
+
+/// Makes the keyboard LEDs show the given lock state. Returns whether the keyboard acknowledged both bytes.
+/// Not called anywhere yet: the kernel has no keyboard driver that tracks the lock keys, input only arrives
+/// over serial. Whatever toggles a lock state later has to call this with the new state.
+#[allow(dead_code)]
+pub fn set_leds(state: LockState) -> bool {
+    let acknowledged = without_interrupts(|| {
+        led_command(state).iter().all(|byte| send_byte(*byte))
+    });
+
+    if !acknowledged {
+        crate::with_serial_port(|serial_logger| serial_logger.log(
+            format_args!("Keyboard did not acknowledge setting the LEDs to {:?}.", state),
+            SerialLoggingLevel::Warning
+        ));
+    }
+    acknowledged
+}
+
+/// Sends a byte to the keyboard and waits for its acknowledgement, sending it again if the keyboard asks for it.
+fn send_byte(byte: u8) -> bool {
+    let mut status = Port::<u8>::new(KEYBOARD_CONTROLLER_STATUS_PORT);
+    let mut data = Port::<u8>::new(KEYBOARD_DATA_PORT);
+
+    for _ in 0..MAX_RETRIES {
+        unsafe {
+            if !poll(&mut status, |status| status & KEYBOARD_CONTROLLER_INPUT_FULL == 0) { return false; }
+            data.write(byte);
+
+            if !poll(&mut status, |status| status & KEYBOARD_CONTROLLER_OUTPUT_FULL != 0) { return false; }
+            match data.read() {
+                KEYBOARD_ACK => return true,
+                KEYBOARD_RESEND => continue,
+                _ => return false
+            }
+        }
+    }
+
+    false
+}
+
+/// Polls the status register until the condition holds, giving up after a while.
+unsafe fn poll(status: &mut Port<u8>, condition: impl Fn(u8) -> bool) -> bool {
+    (0..MAX_POLLS).any(|_| condition(status.read()))
+}
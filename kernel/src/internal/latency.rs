@@ -0,0 +1,73 @@
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+/// Keeps the longest duration recorded so far. Safe to update from interrupt handlers.
+pub struct MaxTracker {
+    max: AtomicU64
+} #[allow(dead_code)] impl MaxTracker {
+    pub const fn new() -> Self {
+        Self { max: AtomicU64::new(0) }
+    }
+
+    /// Records a duration, returning whether it is the new worst case.
+    pub fn record(&self, duration: u64) -> bool {
+        self.max.fetch_max(duration, Ordering::Relaxed) < duration
+    }
+
+    /// Returns the longest duration recorded so far.
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    /// Forgets the recorded durations.
+    pub fn reset(&self) {
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The worst case durations in TSC cycles since boot or the last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IrqLatency {
+    /// The longest time an instrumented section kept interrupts disabled.
+    pub disabled_cycles: u64,
+    /// The longest time an interrupt handler body took.
+    pub handler_cycles: u64
+}
+
+static MAX_DISABLED: MaxTracker = MaxTracker::new();
+static MAX_HANDLER: MaxTracker = MaxTracker::new();
+
+/// Runs a function with interrupts disabled like [`interrupts::without_interrupts`],
+/// recording how long interrupts stayed disabled.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    interrupts::without_interrupts(|| {
+        let start = unsafe { _rdtsc() };
+        let result = f();
+        MAX_DISABLED.record(unsafe { _rdtsc() }.wrapping_sub(start));
+        result
+    })
+}
+
+/// Runs the body of an interrupt handler, recording how long it took.
+pub fn measure_handler<R>(f: impl FnOnce() -> R) -> R {
+    let start = unsafe { _rdtsc() };
+    let result = f();
+    MAX_HANDLER.record(unsafe { _rdtsc() }.wrapping_sub(start));
+    result
+}
+
+/// Returns the worst case durations recorded since boot or the last reset.
+pub fn max_irq_latency() -> IrqLatency {
+    IrqLatency {
+        disabled_cycles: MAX_DISABLED.max(),
+        handler_cycles: MAX_HANDLER.max()
+    }
+}
+
+/// Forgets the recorded worst case durations.
+#[allow(dead_code)]
+pub fn reset_irq_latency() {
+    MAX_DISABLED.reset();
+    MAX_HANDLER.reset();
+}
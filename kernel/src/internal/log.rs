@@ -0,0 +1,65 @@
+use core::fmt;
+use core::fmt::Write;
+use crate::internal::serial::SerialLoggingLevel;
+
+/// Logs a message at debug level to the global serial logger.
+#[macro_export]
+macro_rules! kdebug {
+    ($($arg:tt)*) => {
+        $crate::internal::log::log(format_args!($($arg)*), $crate::internal::serial::SerialLoggingLevel::Debug)
+    };
+}
+
+/// Logs a message at info level to the global serial logger.
+#[macro_export]
+macro_rules! kinfo {
+    ($($arg:tt)*) => {
+        $crate::internal::log::log(format_args!($($arg)*), $crate::internal::serial::SerialLoggingLevel::Info)
+    };
+}
+
+/// Logs a message at warning level to the global serial logger.
+#[macro_export]
+macro_rules! kwarn {
+    ($($arg:tt)*) => {
+        $crate::internal::log::log(format_args!($($arg)*), $crate::internal::serial::SerialLoggingLevel::Warning)
+    };
+}
+
+/// Logs a message at error level to the global serial logger.
+#[macro_export]
+macro_rules! kerror {
+    ($($arg:tt)*) => {
+        $crate::internal::log::log(format_args!($($arg)*), $crate::internal::serial::SerialLoggingLevel::Error)
+    };
+}
+
+/// Writes a message to the serial port without a level or timestamp.
+#[macro_export]
+macro_rules! kprint {
+    ($($arg:tt)*) => {
+        $crate::internal::log::print(format_args!($($arg)*))
+    };
+}
+
+/// Writes a message followed by a newline to the serial port without a level or timestamp.
+#[macro_export]
+macro_rules! kprintln {
+    () => {
+        $crate::internal::log::print(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::internal::log::print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Logs a message at the given level to the global serial logger. Does nothing before the serial port is initialized.
+pub fn log(args: fmt::Arguments, level: SerialLoggingLevel) {
+    crate::with_serial_port(|serial_logger| serial_logger.log(args, level));
+}
+
+/// Writes a message to the global serial logger as is. Does nothing before the serial port is initialized.
+#[allow(dead_code)]
+pub fn print(args: fmt::Arguments) {
+    crate::with_serial_port(|serial_logger| serial_logger.write_fmt(args));
+}
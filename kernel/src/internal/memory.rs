@@ -1,4 +1,5 @@
-use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::ops::Range;
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
 use x86_64::{
     PhysAddr,
@@ -9,44 +10,99 @@ use x86_64::{
     VirtAddr
 };
 
+const FRAME_SIZE: u64 = 4096;
+
+/// The patterns the RAM test writes to every word, followed by a pass writing each word's own address.
+const RAM_TEST_PATTERNS: [u64; 4] = [0x0000_0000_0000_0000, 0xFFFF_FFFF_FFFF_FFFF, 0xAAAA_AAAA_AAAA_AAAA, 0x5555_5555_5555_5555];
+/// How many failing words the RAM test logs before only counting them.
+const MAX_LOGGED_RAM_FAILURES: usize = 16;
+
+/// A word of memory that did not read back what was written to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamTestFailure {
+    pub address: u64,
+    pub expected: u64,
+    pub found: u64
+} impl RamTestFailure {
+    /// Returns the bits that are wrong, e.g. a stuck bit shows up in every failure at the same address.
+    pub fn failing_bits(&self) -> u64 {
+        self.expected ^ self.found
+    }
+}
+
 pub struct SimpleBootInfoFrameAllocator {
     memory_regions: &'static MemoryRegions,
-    next: usize,
+    region_index: usize,
+    next_address: u64,
 } impl SimpleBootInfoFrameAllocator {
     pub unsafe fn new(memory_regions: &'static MemoryRegions) -> Self { Self {
-        memory_regions, next: 0,
+        memory_regions, region_index: 0, next_address: 0,
     } }
-
-    fn usable_regions(&self) -> impl Iterator<Item = PhysFrame> {
-        self.memory_regions.iter()
-            .filter(|region| region.kind == MemoryRegionKind::Usable)
-            .map(|region| region.start..region.end)
-            .flat_map(|region_range| region_range.step_by(4096))
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
 } unsafe impl FrameAllocator<Size4KiB> for SimpleBootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_regions().nth(self.next);
-        self.next += 1;
-        frame
+        while let Some(region) = self.memory_regions.get(self.region_index) {
+            if region.kind == MemoryRegionKind::Usable {
+                let address = self.next_address.max(region.start);
+                if address < region.end {
+                    self.next_address = address + 4096;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(address)));
+                }
+            }
+
+            self.region_index += 1;
+            self.next_address = 0;
+        }
+
+        None
     }
 }
 
+/// Hands out the usable frames region by region. Only the page aligned ranges of the usable regions get stored,
+/// so a fragmented memory map costs one range per region instead of one entry per frame.
 pub struct BootInfoFrameAllocator {
-    usable_frames: VecDeque<PhysFrame>,
-} impl BootInfoFrameAllocator {
+    usable_regions: Vec<Range<u64>>,
+    region_index: usize,
+    next_address: u64,
+    free_frames: Vec<PhysFrame>,
+} #[allow(dead_code)] impl BootInfoFrameAllocator {
     pub unsafe fn new(memory_regions: &'static MemoryRegions) -> Self {
-        let usable_frames = memory_regions.iter()
+        let usable_regions = memory_regions.iter()
             .filter(|region| region.kind == MemoryRegionKind::Usable)
-            .map(|region| region.start..region.end)
-            .flat_map(|region_range| region_range.step_by(4096))
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-            .collect::<VecDeque<_>>();
-        Self { usable_frames }
+            .map(|region| align_up(region.start, FRAME_SIZE)..align_down(region.end, FRAME_SIZE))
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        let next_address = usable_regions.first().map_or(0, |range| range.start);
+
+        Self { usable_regions, region_index: 0, next_address, free_frames: Vec::new() }
+    }
+
+    /// Returns a frame to the allocator so it can be handed out again.
+    /// The caller must make sure the frame is no longer mapped or in use.
+    pub fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free_frames.push(frame);
+    }
+
+    /// Returns the next frame that was never handed out, moving on to the next region once one is used up.
+    fn next_usable_frame(&mut self) -> Option<PhysFrame> {
+        while let Some(range) = self.usable_regions.get(self.region_index) {
+            if self.next_address < range.end {
+                let address = self.next_address;
+                self.next_address += FRAME_SIZE;
+                return Some(PhysFrame::containing_address(PhysAddr::new(address)));
+            }
+
+            self.region_index += 1;
+            if let Some(next_range) = self.usable_regions.get(self.region_index) {
+                self.next_address = next_range.start;
+            }
+        }
+
+        None
     }
 } unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        self.usable_frames.pop_front()
+        self.free_frames.pop()
+            .or_else(|| self.next_usable_frame())
     }
 }
 
@@ -65,4 +121,96 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
 
     &mut *page_table_ptr
+}
+
+/// Writes each test pattern to every word of the memory and reads it back, then does the same with every word
+/// holding its own address, which also catches address lines that are shorted or stuck.
+/// `base_address` is the address the first word gets reported with. Returns the number of failing reads.
+pub fn verify_ram_patterns(memory: &mut [u64], base_address: u64, mut on_failure: impl FnMut(RamTestFailure)) -> usize {
+    let mut failures = 0;
+    let mut check = |memory: &mut [u64], expected: &dyn Fn(u64) -> u64| {
+        for (index, word) in memory.iter_mut().enumerate() {
+            unsafe { core::ptr::write_volatile(word, expected(base_address + index as u64 * 8)); }
+        }
+        for (index, word) in memory.iter().enumerate() {
+            let address = base_address + index as u64 * 8;
+            let found = unsafe { core::ptr::read_volatile(word) };
+            if found != expected(address) {
+                failures += 1;
+                on_failure(RamTestFailure { address, expected: expected(address), found });
+            }
+        }
+    };
+
+    for pattern in RAM_TEST_PATTERNS {
+        check(memory, &|_| pattern);
+    }
+    check(memory, &|address| address);
+
+    failures
+}
+
+/// Tests all usable memory through the mapping of the physical memory, logging failing words over serial.
+///
+/// # Safety
+/// Overwrites every usable frame, so it must run before any frame gets handed out, i.e. before the heaps get initialized.
+/// The complete physical memory needs to be mapped at the given offset.
+#[allow(dead_code)]
+pub unsafe fn run_ram_test(memory_regions: &MemoryRegions, physical_memory_offset: VirtAddr) -> usize {
+    let mut tested = 0u64;
+    let mut failures = 0;
+    let mut logged = 0;
+
+    for region in memory_regions.iter().filter(|region| region.kind == MemoryRegionKind::Usable) {
+        let (start, end) = (align_up(region.start, 8), align_down(region.end, 8));
+        if start >= end { continue; }
+
+        let words = ((end - start) / 8) as usize;
+        let memory = core::slice::from_raw_parts_mut((physical_memory_offset + start).as_mut_ptr::<u64>(), words);
+        failures += verify_ram_patterns(memory, start, |failure| {
+            if logged < MAX_LOGGED_RAM_FAILURES {
+                crate::kerror!("RAM test failed at {:#018x}: expected {:#018x}, found {:#018x} (bits {:#018x}).",
+                    failure.address, failure.expected, failure.found, failure.failing_bits()
+                );
+                logged += 1;
+            }
+        });
+        tested += end - start;
+    }
+
+    if failures == 0 {
+        crate::kinfo!("RAM test passed for {} KiB of usable memory.", tested / 1024);
+    } else {
+        crate::kerror!("RAM test found {} failing reads in {} KiB of usable memory!", failures, tested / 1024);
+    }
+
+    failures
+}
+
+/// Logs every region of the memory map with its start, end, size and kind as a table,
+/// followed by the total usable and reserved memory.
+#[allow(dead_code)]
+pub fn log_memory_map(memory_regions: &MemoryRegions) {
+    crate::kinfo!("Memory map with {} regions:", memory_regions.len());
+    crate::kinfo!("{:>18} {:>18} {:>12}  {}", "Start", "End", "Size (KiB)", "Kind");
+
+    let (mut usable, mut reserved) = (0u64, 0u64);
+    for region in memory_regions.iter() {
+        let size = region.end - region.start;
+        if region.kind == MemoryRegionKind::Usable { usable += size; } else { reserved += size; }
+
+        crate::kinfo!("{:#018x} {:#018x} {:>12}  {:?}", region.start, region.end, size / 1024, region.kind);
+    }
+
+    crate::kinfo!("Usable memory: {} KiB, reserved memory: {} KiB.", usable / 1024, reserved / 1024);
+}
+
+#[inline]
+fn align_up(address: u64, align: u64) -> u64 {
+    (address + align - 1) & !(align - 1)
+}
+
+#[inline]
+fn align_down(address: u64, align: u64) -> u64 {
+    address & !(align - 1)
 }
\ No newline at end of file
@@ -2,4 +2,15 @@ pub mod memory;
 pub mod allocator;
 pub mod serial;
 pub mod idt;
-pub mod gdt;
\ No newline at end of file
+pub mod gdt;
+pub mod timer;
+pub mod rng;
+pub mod latency;
+pub mod base64;
+pub mod queue;
+pub mod trace;
+pub mod power;
+pub mod qemu_exit;
+pub mod backtrace;
+pub mod log;
+pub mod keyboard;
\ No newline at end of file
@@ -0,0 +1,63 @@
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::instructions::port::Port;
+use crate::internal::serial::SerialLoggingLevel;
+
+const RESET_CONTROL_PORT: u16 = 0xCF9;
+/// Requests a hard reset (bit 1) and then triggers it (bit 2).
+const RESET_CONTROL_HARD_RESET: u8 = 0x02;
+const RESET_CONTROL_TRIGGER: u8 = 0x04;
+
+const KEYBOARD_CONTROLLER_STATUS_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_COMMAND_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 0x02;
+/// Output line 0 of the keyboard controller is wired to the CPU reset line.
+const KEYBOARD_CONTROLLER_RESET_LINE: u8 = 0x01;
+
+/// ACPI power management ports and values that power off common emulators: QEMU, older QEMU/Bochs and VirtualBox.
+const ACPI_SHUTDOWN_PORTS: [(u16, u16); 3] = [(0x604, 0x2000), (0xB004, 0x2000), (0x4004, 0x3400)];
+
+/// Returns the keyboard controller command that pulses the given output lines low for about 6ms.
+/// Lines set in the mask get pulsed, so `0x01` (the reset line) results in `0xFE`.
+pub const fn keyboard_controller_pulse_command(lines: u8) -> u8 {
+    0xF0 | (!lines & 0x0F)
+}
+
+/// Resets the machine. First tries the reset control register at 0xCF9,
+/// then pulses the reset line of the 8042 keyboard controller. Halts if neither worked.
+pub fn reboot() -> ! {
+    crate::with_serial_port(|serial_logger| {
+        serial_logger.log(format_args!("Rebooting."), SerialLoggingLevel::Info);
+    });
+    interrupts::disable();
+
+    unsafe {
+        let mut reset_control = Port::<u8>::new(RESET_CONTROL_PORT);
+        reset_control.write(RESET_CONTROL_HARD_RESET);
+        reset_control.write(RESET_CONTROL_HARD_RESET | RESET_CONTROL_TRIGGER);
+
+        let mut status = Port::<u8>::new(KEYBOARD_CONTROLLER_STATUS_PORT);
+        while status.read() & KEYBOARD_CONTROLLER_INPUT_FULL != 0 {}
+        Port::<u8>::new(KEYBOARD_CONTROLLER_COMMAND_PORT)
+            .write(keyboard_controller_pulse_command(KEYBOARD_CONTROLLER_RESET_LINE));
+    }
+
+    halt_forever()
+}
+
+/// Powers off the machine. Tries the ACPI shutdown ports used by common emulators in order. Halts if none worked.
+pub fn shutdown() -> ! {
+    crate::with_serial_port(|serial_logger| {
+        serial_logger.log(format_args!("Shutting down."), SerialLoggingLevel::Info);
+    });
+    interrupts::disable();
+
+    for (port, value) in ACPI_SHUTDOWN_PORTS {
+        unsafe { Port::<u16>::new(port).write(value); }
+    }
+
+    halt_forever()
+}
+
+fn halt_forever() -> ! {
+    loop { hlt(); }
+}
@@ -0,0 +1,21 @@
+use x86_64::instructions::hlt;
+use x86_64::instructions::port::Port;
+
+/// I/O port of the `isa-debug-exit` device the QEMU runners attach.
+const ISA_DEBUG_EXIT_PORT: u16 = 0xF4;
+
+/// Exit codes reported to the host. QEMU exits with `(code << 1) | 1`, which the runners map back.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11
+}
+
+/// Makes QEMU exit with the given code. Halts if the kernel is not running in QEMU with the `isa-debug-exit` device.
+pub fn exit_qemu(exit_code: QemuExitCode) -> ! {
+    unsafe { Port::<u32>::new(ISA_DEBUG_EXIT_PORT).write(exit_code as u32); }
+
+    loop { hlt(); }
+}
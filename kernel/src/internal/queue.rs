@@ -0,0 +1,74 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+struct RingBuffer<T: Copy, const N: usize> {
+    buffer: [Option<T>; N],
+    head: usize,
+    len: usize
+}
+
+/// A fixed-capacity queue that does not allocate, meant for handing data from interrupt handlers to the kernel.
+/// Items pushed while the queue is full are dropped and counted.
+pub struct Queue<T: Copy, const N: usize> {
+    inner: Mutex<RingBuffer<T, N>>,
+    dropped: AtomicUsize
+} #[allow(dead_code)] impl<T: Copy, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self { Self {
+        inner: Mutex::new(RingBuffer {
+            buffer: [None; N],
+            head: 0,
+            len: 0
+        }),
+        dropped: AtomicUsize::new(0)
+    } }
+
+    /// Adds an item to the end of the queue. Returns false and drops the item if the queue is full.
+    /// Interrupts are disabled while the queue is locked, so it is safe to push from both interrupt handlers
+    /// and the kernel without an interrupt handler deadlocking on the lock.
+    pub fn push(&self, item: T) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut ring = self.inner.lock();
+
+            if ring.len == N {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+
+            let index = (ring.head + ring.len) % N;
+            ring.buffer[index] = Some(item);
+            ring.len += 1;
+            true
+        })
+    }
+
+    /// Takes the oldest item out of the queue.
+    /// Interrupts are disabled while the queue is locked, so an interrupt handler pushing cannot deadlock.
+    pub fn pop(&self) -> Option<T> {
+        interrupts::without_interrupts(|| {
+            let mut ring = self.inner.lock();
+            if ring.len == 0 { return None; }
+
+            let head = ring.head;
+            let item = ring.buffer[head].take();
+            ring.head = (head + 1) % N;
+            ring.len -= 1;
+            item
+        })
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        interrupts::without_interrupts(|| self.inner.lock().len)
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how many items were dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,59 @@
+use core::arch::x86_64::_rdtsc;
+use spin::Mutex;
+
+/// A SplitMix64 pseudo random number generator. Not suited for cryptography,
+/// but fast and deterministic for a given seed, which makes it easy to reproduce a run.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64
+} #[allow(dead_code)] impl SplitMix64 {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a random number in `min..max`. Panics if the range is empty.
+    pub fn next_range(&mut self, min: u64, max: u64) -> u64 {
+        if min >= max { panic!("Cannot pick a random number from the empty range {}..{}!", min, max); }
+
+        let span = max - min;
+        // Rejects the top values that would make the lower numbers more likely.
+        let zone = u64::MAX - u64::MAX % span;
+        loop {
+            let value = self.next_u64();
+            if value < zone { return min + value % span; }
+        }
+    }
+}
+
+static RNG: Mutex<SplitMix64> = Mutex::new(SplitMix64::new(0));
+
+/// Seeds the global generator from the time stamp counter.
+pub fn init() {
+    seed(unsafe { _rdtsc() });
+}
+
+/// Seeds the global generator with a fixed value, e.g. to reproduce a run.
+pub fn seed(seed: u64) {
+    *RNG.lock() = SplitMix64::new(seed);
+}
+
+/// Returns the next 64 random bits of the global generator.
+#[allow(dead_code)]
+pub fn next_u64() -> u64 {
+    RNG.lock().next_u64()
+}
+
+/// Returns a random number in `min..max` from the global generator. Panics if the range is empty.
+#[allow(dead_code)]
+pub fn next_range(min: u64, max: u64) -> u64 {
+    RNG.lock().next_range(min, max)
+}
@@ -1,7 +1,31 @@
+use alloc::string::String;
 use core::fmt;
 use core::fmt::Write;
+use x86_64::instructions::port::{Port, PortReadOnly};
+use crate::internal::queue::Queue;
+use crate::internal::timer;
+
+const INPUT_BUFFER_SIZE: usize = 256;
+
+/// The standard serial ports of a PC.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialPort {
+    Com1, Com2, Com3, Com4
+} impl SerialPort {
+    /// Returns the I/O port base address of the serial port.
+    pub fn base(self) -> u16 {
+        match self {
+            Self::Com1 => 0x3F8,
+            Self::Com2 => 0x2F8,
+            Self::Com3 => 0x3E8,
+            Self::Com4 => 0x2E8
+        }
+    }
+}
 
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SerialLoggingLevel {
     Debug,
     Info,
@@ -20,17 +44,81 @@ pub enum SerialLoggingLevel {
     }
 }
 
+/// Formats milliseconds as `HH:MM:SS.mmm`.
+struct Timestamp(u64); impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let milliseconds = self.0 % 1000;
+        let seconds = self.0 / 1000 % 60;
+        let minutes = self.0 / 60_000 % 60;
+        let hours = self.0 / 3_600_000;
+        write!(f, "{:02}:{:02}:{:02}.{:03}", hours, minutes, seconds, milliseconds)
+    }
+}
+
 pub struct SerialPortLogger {
-    port: uart_16550::SerialPort
-} impl SerialPortLogger {
+    port: uart_16550::SerialPort,
+    data: Port<u8>,
+    line_status: PortReadOnly<u8>,
+    min_level: SerialLoggingLevel,
+    timestamps: bool
+} #[allow(dead_code)] impl SerialPortLogger {
+    /// Initializes a logger on COM1.
     pub unsafe fn init() -> Self {
-        let mut port = unsafe { uart_16550::SerialPort::new(0x3F8) };
+        Self::init_port(SerialPort::Com1)
+    }
+
+    /// Initializes a logger on one of the standard serial ports.
+    pub unsafe fn init_port(serial_port: SerialPort) -> Self {
+        Self::init_base(serial_port.base())
+    }
+
+    /// Initializes a logger on the serial port with the given I/O port base address.
+    /// Every port should only have a single logger.
+    pub unsafe fn init_base(base: u16) -> Self {
+        let mut port = unsafe { uart_16550::SerialPort::new(base) };
         port.init();
-        Self { port }
+        Self {
+            port,
+            data: Port::new(base),
+            line_status: PortReadOnly::new(base + 5),
+            min_level: SerialLoggingLevel::Info,
+            timestamps: true
+        }
+    }
+
+    /// Sets whether log lines get prefixed with the uptime.
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Sets the lowest level that still gets logged. Messages below it are dropped.
+    pub fn set_min_level(&mut self, level: SerialLoggingLevel) {
+        self.min_level = level;
     }
 
     pub fn log(&mut self, args: fmt::Arguments, level: SerialLoggingLevel) {
-        self.port.write_fmt(format_args!("[{}]: {}\n", level.as_str(), args)).unwrap();
+        if level < self.min_level { return; }
+
+        if self.timestamps {
+            self.port.write_fmt(format_args!("[{}][{}]: {}\n", Timestamp(timer::uptime_ms()), level.as_str(), args)).unwrap();
+        } else {
+            self.port.write_fmt(format_args!("[{}]: {}\n", level.as_str(), args)).unwrap();
+        }
+    }
+
+    /// Reads a byte from the serial port if one has been received.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            if self.line_status.read() & 1 != 0 {
+                Some(self.data.read())
+            } else { None }
+        }
+    }
+
+    /// Sends a single byte over the serial port.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.port.send(byte);
     }
 } impl Write for SerialPortLogger {
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -43,4 +131,44 @@ pub struct SerialPortLogger {
     fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
         self.port.write_fmt(args)
     }
+}
+
+static INPUT_BUFFER: Queue<u8, INPUT_BUFFER_SIZE> = Queue::new();
+
+/// Queues a byte received over the serial port. Meant to be called from the serial interrupt handler.
+/// Bytes are dropped if the input buffer is full.
+pub fn push_input_byte(byte: u8) {
+    INPUT_BUFFER.push(byte);
+}
+
+/// Takes the oldest received byte out of the input buffer.
+pub fn pop_input_byte() -> Option<u8> {
+    INPUT_BUFFER.pop()
+}
+
+/// Returns how many received bytes were dropped because the input buffer was full.
+#[allow(dead_code)]
+pub fn dropped_input_bytes() -> usize {
+    INPUT_BUFFER.dropped()
+}
+
+/// Assembles bytes received over the serial port into lines terminated by a newline.
+pub struct SerialLineReader {
+    line: String
+} impl SerialLineReader {
+    pub fn new() -> Self { Self {
+        line: String::new()
+    } }
+
+    /// Adds a byte to the current line. Returns the line once it is terminated by a newline.
+    pub fn push_byte(&mut self, byte: u8) -> Option<String> {
+        match byte {
+            b'\n' => Some(core::mem::take(&mut self.line)),
+            b'\r' => None,
+            _ => {
+                self.line.push(byte as char);
+                None
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,179 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::instructions::port::Port;
+
+const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+const PIT_CHANNEL_0_PORT: u16 = 0x40;
+const PIT_COMMAND_PORT: u16 = 0x43;
+
+/// Channel 0, lobyte/hibyte access, square wave generator, binary mode.
+const PIT_COMMAND: u8 = 0x36;
+
+pub const TIMER_FREQUENCY: u32 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static DIVISOR: AtomicU32 = AtomicU32::new(65536);
+
+/// Programs the PIT to fire the timer interrupt at the given frequency in Hz.
+pub fn init(frequency: u32) {
+    let divisor = divisor_for(frequency);
+
+    unsafe {
+        let mut command_port = Port::<u8>::new(PIT_COMMAND_PORT);
+        let mut channel_0_port = Port::<u8>::new(PIT_CHANNEL_0_PORT);
+
+        command_port.write(PIT_COMMAND);
+        channel_0_port.write((divisor & 0xFF) as u8);
+        channel_0_port.write((divisor >> 8) as u8);
+    }
+
+    DIVISOR.store(divisor as u32, Ordering::SeqCst);
+}
+
+/// Advances the tick counter. Should only get called by the timer interrupt handler.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of timer interrupts since the interrupts were enabled.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since the interrupts were enabled in milliseconds.
+pub fn uptime_ms() -> u64 {
+    ticks() * DIVISOR.load(Ordering::Relaxed) as u64 * 1000 / PIT_BASE_FREQUENCY as u64
+}
+
+/// Waits for at least the given amount of milliseconds. Interrupts need to be enabled.
+#[allow(dead_code)]
+pub fn sleep_ms(ms: u64) {
+    if !interrupts::are_enabled() {
+        panic!("Cannot sleep with interrupts disabled!");
+    }
+
+    let target = uptime_ms() + ms;
+    while uptime_ms() < target {
+        hlt();
+    }
+}
+
+/// Decides when the next tick of something running at a fixed interval of uptime is due.
+/// Ticks that were missed are not made up, the next one is scheduled an interval after the late one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickScheduler {
+    interval_ms: u64,
+    last_tick_ms: u64
+} impl TickScheduler {
+    /// Creates a scheduler whose first tick is due one interval after `now_ms`.
+    pub fn new(interval_ms: u64, now_ms: u64) -> Self {
+        Self { interval_ms: interval_ms.max(1), last_tick_ms: now_ms }
+    }
+
+    /// Returns the milliseconds elapsed since the last tick if a new tick is due at `now_ms`, and starts the next interval.
+    pub fn poll(&mut self, now_ms: u64) -> Option<u64> {
+        let elapsed_ms = now_ms.saturating_sub(self.last_tick_ms);
+        if elapsed_ms < self.interval_ms { return None; }
+
+        self.last_tick_ms = now_ms;
+        Some(elapsed_ms)
+    }
+}
+
+/// How many periodic timers can be registered at the same time.
+pub const MAX_PERIODIC_TIMERS: usize = 16;
+
+static PERIODIC_TIMERS: Mutex<PeriodicTimers<MAX_PERIODIC_TIMERS>> = Mutex::new(PeriodicTimers::new());
+
+/// Identifies a registered periodic timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(usize);
+
+#[derive(Debug, Clone, Copy)]
+struct PeriodicTimer {
+    interval_ms: u64,
+    next_due_ms: u64,
+    callback: fn()
+}
+
+/// A fixed number of callbacks that fire at their own intervals. Does not allocate, so it can be driven
+/// from the kernel's main loop with the current time. Callbacks are plain functions and run outside of the lock,
+/// so they can register or cancel timers themselves.
+pub struct PeriodicTimers<const N: usize> {
+    timers: [Option<PeriodicTimer>; N]
+} #[allow(dead_code)] impl<const N: usize> PeriodicTimers<N> {
+    pub const fn new() -> Self {
+        Self { timers: [None; N] }
+    }
+
+    /// Registers a callback that first fires once the interval has passed after `now_ms` and then every interval.
+    /// Returns `None` if all slots are taken.
+    pub fn register(&mut self, now_ms: u64, interval_ms: u64, callback: fn()) -> Option<TimerId> {
+        let interval_ms = interval_ms.max(1);
+        let index = self.timers.iter().position(|timer| timer.is_none())?;
+
+        self.timers[index] = Some(PeriodicTimer { interval_ms, next_due_ms: now_ms + interval_ms, callback });
+        Some(TimerId(index))
+    }
+
+    /// Removes a timer so its callback does not fire anymore.
+    pub fn cancel(&mut self, id: TimerId) {
+        if let Some(timer) = self.timers.get_mut(id.0) {
+            *timer = None;
+        }
+    }
+
+    /// Returns the callbacks that are due at `now_ms` and schedules their next run.
+    /// Each timer fires at most once per call. A timer that fell behind by more than one interval skips the missed runs.
+    pub fn take_due(&mut self, now_ms: u64) -> [Option<fn()>; N] {
+        let mut due = [None; N];
+
+        for (slot, timer) in due.iter_mut().zip(self.timers.iter_mut()) {
+            let Some(timer) = timer.as_mut() else { continue; };
+            if now_ms < timer.next_due_ms { continue; }
+
+            timer.next_due_ms += timer.interval_ms;
+            if timer.next_due_ms <= now_ms {
+                timer.next_due_ms = now_ms + timer.interval_ms;
+            }
+            *slot = Some(timer.callback);
+        }
+
+        due
+    }
+
+    /// Fires every callback that is due at `now_ms`.
+    pub fn run_due(&mut self, now_ms: u64) {
+        for callback in self.take_due(now_ms).into_iter().flatten() {
+            callback();
+        }
+    }
+}
+
+/// Registers a callback with the global periodic timers, firing every `interval_ms` milliseconds of uptime
+/// once [`run_due_timers`] gets called. Returns `None` if all slots are taken.
+#[allow(dead_code)]
+pub fn register_periodic(interval_ms: u64, callback: fn()) -> Option<TimerId> {
+    interrupts::without_interrupts(|| PERIODIC_TIMERS.lock().register(uptime_ms(), interval_ms, callback))
+}
+
+/// Removes a timer from the global periodic timers.
+#[allow(dead_code)]
+pub fn cancel_periodic(id: TimerId) {
+    interrupts::without_interrupts(|| PERIODIC_TIMERS.lock().cancel(id));
+}
+
+/// Fires the callbacks of the global periodic timers that are due. Meant to be called from the kernel's main loop,
+/// not from an interrupt handler.
+pub fn run_due_timers() {
+    let due = interrupts::without_interrupts(|| PERIODIC_TIMERS.lock().take_due(uptime_ms()));
+    for callback in due.into_iter().flatten() {
+        callback();
+    }
+}
+
+/// Computes the PIT divisor for the given frequency, clamped to what the PIT supports.
+fn divisor_for(frequency: u32) -> u16 {
+    (PIT_BASE_FREQUENCY / frequency.max(1)).clamp(1, u16::MAX as u32) as u16
+}
@@ -0,0 +1,36 @@
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::internal::serial::SerialLoggingLevel;
+
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates a [`Span`] with the given name that lasts until the end of the current scope.
+#[macro_export]
+macro_rules! trace_span {
+    ($name:expr) => {
+        $crate::internal::trace::Span::new($name)
+    };
+}
+
+/// Measures the cycles between its creation and when it gets dropped and logs them at debug level.
+/// Spans created while another span is alive are nested inside of it.
+pub struct Span {
+    name: &'static str,
+    start: u64,
+    depth: usize
+} impl Span {
+    pub fn new(name: &'static str) -> Self {
+        let depth = DEPTH.fetch_add(1, Ordering::SeqCst);
+        Self { name, start: unsafe { _rdtsc() }, depth }
+    }
+} impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = unsafe { _rdtsc() }.wrapping_sub(self.start);
+        DEPTH.fetch_sub(1, Ordering::SeqCst);
+
+        crate::with_serial_port(|serial_logger| serial_logger.log(
+            format_args!("{:indent$}{} took {} cycles.", "", self.name, elapsed, indent = self.depth * 2),
+            SerialLoggingLevel::Debug
+        ));
+    }
+}
@@ -1,44 +1,254 @@
+use core::fmt;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::Size4KiB;
 use crate::api::display::Fonts;
-use crate::drivers::display::{CommonDisplayDriver, DisplayDriverType};
-use crate::internal::serial::{SerialLoggingLevel, SerialPortLogger};
+use crate::drivers::display::DisplayDriverType;
+use crate::internal::{latency, power};
+use crate::internal::timer::{self, TickScheduler};
+use crate::internal::serial::{self, SerialLineReader, SerialLoggingLevel};
 use crate::managers::display::{DisplayManager, DisplayMode};
+use crate::systems::shell::Shell;
+#[cfg(feature = "input-replay")]
+use crate::systems::replay::{self, InputReplay};
+
+/// What the kernel's main loop should currently be doing.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelState {
+    /// The main loop keeps ticking the kernel.
+    Running,
+    /// The main loop idles until the kernel gets resumed.
+    Paused,
+    /// The main loop exits and the kernel halts.
+    ShuttingDown { reason: &'static str },
+    /// The kernel has stopped for good.
+    Halted
+}
+
+/// A step of the boot sequence that failed, with the error it failed with.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum KernelInitError {
+    /// The frame buffer was passed without its layout.
+    FrameBufferInfoMissing,
+    /// The bootloader did not map the physical memory, so page tables cannot be accessed.
+    PhysicalMemoryNotMapped,
+    /// Mapping the pages of the initial heap failed.
+    InitialHeap(MapToError<Size4KiB>),
+    /// Mapping the pages of the main heap failed.
+    MainHeap(MapToError<Size4KiB>)
+} impl fmt::Display for KernelInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KernelInitError::FrameBufferInfoMissing => write!(f, "Frame buffer info not found!"),
+            KernelInitError::PhysicalMemoryNotMapped => write!(f,
+                "Physical memory is not mapped! The bootloader config has to request a physical memory mapping."
+            ),
+            KernelInitError::InitialHeap(error) => write!(f, "Initial heap initialization failed: {}", describe_map_to_error(error)),
+            KernelInitError::MainHeap(error) => write!(f, "Main heap initialization failed: {}", describe_map_to_error(error))
+        }
+    }
+}
+
+/// How often the text cursor blinks.
+const CURSOR_BLINK_INTERVAL_MS: u64 = 500;
+/// How often render metrics and draw times get logged.
+const STATS_INTERVAL_MS: u64 = 10_000;
 
 pub struct Kernel<'a> {
     display_manager: DisplayManager<'a>,
-    serial_logger: &'a mut SerialPortLogger,
-    pub running: bool
-} impl<'a> Kernel<'a> {
-    pub fn new(display_manager: DisplayManager<'a>, serial_logger: &'a mut SerialPortLogger) -> Self {
+    serial_line_reader: SerialLineReader,
+    shell: Shell,
+    state: KernelState,
+    cursor_blink: TickScheduler,
+    stats_log: TickScheduler,
+    #[cfg(feature = "input-replay")]
+    input_replay: InputReplay
+} #[allow(dead_code)] impl<'a> Kernel<'a> {
+    pub fn new(display_manager: DisplayManager<'a>) -> Self {
         Self {
             display_manager,
-            serial_logger,
-            running: true
+            serial_line_reader: SerialLineReader::new(),
+            shell: Shell::new("C:\\> "),
+            state: KernelState::Running,
+            cursor_blink: TickScheduler::new(CURSOR_BLINK_INTERVAL_MS, timer::uptime_ms()),
+            stats_log: TickScheduler::new(STATS_INTERVAL_MS, timer::uptime_ms()),
+            #[cfg(feature = "input-replay")]
+            input_replay: InputReplay::from_text(replay::BOOT_SCRIPT, replay::BOOT_SCRIPT_DELAY_TICKS)
         }
     }
 
+    /// Returns the current state of the kernel.
+    pub fn state(&self) -> KernelState {
+        self.state
+    }
+
+    /// Pauses the kernel. Only possible while it is running. Returns whether the state changed.
+    pub fn pause(&mut self) -> bool {
+        self.transition(KernelState::Paused)
+    }
+
+    /// Resumes the kernel. Only possible while it is paused. Returns whether the state changed.
+    pub fn resume(&mut self) -> bool {
+        self.transition(KernelState::Running)
+    }
+
+    /// Makes the main loop exit so the kernel halts. Only possible while it is running or paused.
+    /// Returns whether the state changed.
+    pub fn request_shutdown(&mut self, reason: &'static str) -> bool {
+        self.transition(KernelState::ShuttingDown { reason })
+    }
+
     pub fn init(&mut self) {
         self.display_manager.set_mode(DisplayMode::Text(Fonts::Font9x18B));
 
-        self.serial_logger.log(format_args!("Kernel told display manager to use display mode {}.",
-            self.display_manager.get_display_mode()),
+        let display_mode = self.display_manager.get_display_mode();
+        crate::with_serial_port(|serial_logger| serial_logger.log(
+            format_args!("Kernel told display manager to use display mode {}.", display_mode),
             SerialLoggingLevel::Info
-        );
+        ));
+
+        match self.display_manager.get_driver() {
+            DisplayDriverType::Text(driver, _) => {
+                self.shell.start(driver);
+            }, _ => panic!("Unsupported display driver type!")
+        }
     }
 
-    pub fn tick(&mut self, tick: u64) {
+    /// Runs one tick of the kernel at the given uptime. `elapsed_ms` is the real time since the previous tick.
+    pub fn tick(&mut self, uptime_ms: u64, _elapsed_ms: u64) {
+        #[cfg(feature = "input-replay")]
+        self.input_replay.tick();
+        self.handle_serial_input();
+        #[cfg(feature = "input-replay")]
+        self.check_input_replay();
+
         match self.display_manager.get_driver() {
             DisplayDriverType::Text(driver, _) => {
-                driver.write_string("C:\\> ");
-                if tick % 3000 == 0 { driver.blink(); }
-                driver.draw_all();
-                driver.clear_buffer();
+                if self.cursor_blink.poll(uptime_ms).is_some() { driver.blink(); }
             }, _ => panic!("Unsupported display driver type!")
         }
+        self.display_manager.draw_all();
+
+        if self.stats_log.poll(uptime_ms).is_some() {
+            let draw_stats = self.display_manager.draw_stats();
+            if let DisplayDriverType::Text(driver, _) = self.display_manager.get_driver() {
+                let metrics = driver.render_metrics();
+                crate::with_serial_port(|serial_logger| serial_logger.log(format_args!(
+                    "Render metrics: {} dirty regions, {} segments, {} draw_text calls, {} draw_char calls, ~{} pixels.",
+                    metrics.dirty_regions, metrics.segments,
+                    metrics.draw_text_calls, metrics.draw_char_calls, metrics.pixels
+                ), SerialLoggingLevel::Debug));
+            }
+            crate::with_serial_port(|serial_logger| serial_logger.log(format_args!(
+                "Draw time over the last {} frames: {} min, {} max, {} average cycles.",
+                draw_stats.frames, draw_stats.min, draw_stats.max, draw_stats.average
+            ), SerialLoggingLevel::Debug));
+
+            let irq_latency = latency::max_irq_latency();
+            crate::with_serial_port(|serial_logger| serial_logger.log(format_args!(
+                "Worst case IRQ latency: interrupts disabled for {} cycles, handler took {} cycles.",
+                irq_latency.disabled_cycles, irq_latency.handler_cycles
+            ), SerialLoggingLevel::Debug));
+        }
+    }
+
+    /// Changes the state if the transition is valid, logging a warning otherwise.
+    fn transition(&mut self, to: KernelState) -> bool {
+        if !is_valid_transition(self.state, to) {
+            crate::with_serial_port(|serial_logger| serial_logger.log(
+                format_args!("Rejected kernel state transition from {:?} to {:?}.", self.state, to),
+                SerialLoggingLevel::Warning
+            ));
+            return false;
+        }
+
+        self.state = to;
+        true
+    }
+
+    fn handle_serial_input(&mut self) {
+        while let Some(byte) = serial::pop_input_byte() {
+            let byte = if byte == b'\r' { b'\n' } else { byte };
+            let line = self.serial_line_reader.push_byte(byte);
+
+            crate::with_serial_port(|serial_logger| {
+                serial_logger.write_byte(byte);
+                if let Some(line) = line {
+                    serial_logger.log(format_args!("Received serial input: {}", line), SerialLoggingLevel::Debug);
+                }
+            });
+
+            if let DisplayDriverType::Text(driver, _) = self.display_manager.get_driver() {
+                self.shell.handle_char(byte as char, driver);
+            }
+        }
+    }
+
+    /// Checks the screen for the expected output once the boot script has been replayed.
+    /// With the `qemu-test` feature, QEMU exits with the result.
+    #[cfg(feature = "input-replay")]
+    fn check_input_replay(&mut self) {
+        if !self.input_replay.take_finished() { return; }
+
+        let passed = match self.display_manager.get_driver() {
+            DisplayDriverType::Text(driver, _) => replay::screen_contains(driver, replay::BOOT_SCRIPT_EXPECTED),
+            _ => false
+        };
+
+        crate::with_serial_port(|serial_logger| serial_logger.log(
+            format_args!("Input replay {}: expected {:?} on screen.", if passed { "passed" } else { "failed" }, replay::BOOT_SCRIPT_EXPECTED),
+            if passed { SerialLoggingLevel::Info } else { SerialLoggingLevel::Error }
+        ));
+
+        if cfg!(feature = "qemu-test") {
+            crate::internal::qemu_exit::exit_qemu(if passed {
+                crate::internal::qemu_exit::QemuExitCode::Success
+            } else { crate::internal::qemu_exit::QemuExitCode::Failed });
+        }
+    }
+
+    /// Resets the machine. See [`power::reboot`] for how.
+    pub fn reboot(&mut self) -> ! {
+        self.request_shutdown("reboot");
+        power::reboot()
+    }
+
+    /// Powers off the machine. See [`power::shutdown`] for how.
+    pub fn shutdown(&mut self) -> ! {
+        self.request_shutdown("shutdown");
+        power::shutdown()
     }
 
     pub fn halt(&mut self) -> ! {
-        self.serial_logger.log(format_args!("Kernel is halting."), SerialLoggingLevel::Info);
+        let reason = match self.state {
+            KernelState::ShuttingDown { reason } => reason,
+            _ => "halt requested"
+        };
+        self.state = KernelState::Halted;
+
+        crate::with_serial_port(|serial_logger| {
+            serial_logger.log(format_args!("Kernel is halting ({}).", reason), SerialLoggingLevel::Info);
+        });
 
         loop {}
     }
+}
+
+fn is_valid_transition(from: KernelState, to: KernelState) -> bool {
+    matches!((from, to),
+        (KernelState::Running, KernelState::Paused) |
+        (KernelState::Paused, KernelState::Running) |
+        (KernelState::Running | KernelState::Paused, KernelState::ShuttingDown { .. }) |
+        (KernelState::ShuttingDown { .. }, KernelState::Halted)
+    )
+}
+
+/// Describes why mapping a page failed.
+fn describe_map_to_error(error: &MapToError<Size4KiB>) -> &'static str {
+    match error {
+        MapToError::FrameAllocationFailed => "no free frame left to map the page or its page tables to.",
+        MapToError::ParentEntryHugePage => "the page lies inside a huge page that is already mapped.",
+        MapToError::PageAlreadyMapped(_) => "the page, or one of its guard pages, is already mapped."
+    }
 }
\ No newline at end of file
@@ -13,13 +13,15 @@ use core::sync::atomic::{AtomicBool, Ordering};
 
 use bootloader_api::{
     config::{BootloaderConfig, Mapping},
-    info::FrameBufferInfo
+    info::{FrameBufferInfo, PixelFormat}
 };
+use spin::{Mutex, Once};
 use x86_64::VirtAddr;
 use crate::drivers::display::DisplayDriverType;
 use crate::internal::memory::{BootInfoFrameAllocator, SimpleBootInfoFrameAllocator};
-use crate::internal::serial::{SerialLoggingLevel, SerialPortLogger};
-use crate::kernel::Kernel;
+use crate::internal::serial::{SerialLoggingLevel, SerialPort, SerialPortLogger};
+use crate::internal::timer::TickScheduler;
+use crate::kernel::{Kernel, KernelInitError, KernelState};
 use crate::managers::display::{DisplayManager, DisplayMode, DisplayType};
 
 mod internal;
@@ -30,6 +32,8 @@ mod systems;
 mod drivers;
 mod managers;
 
+/// The kernel accesses page tables and frames through a mapping of the complete physical memory,
+/// so the bootloader has to be told to create it. Without it, booting stops before the heap is set up.
 const BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
     config.mappings.physical_memory = Some(Mapping::Dynamic);
@@ -40,84 +44,155 @@ bootloader_api::entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
 fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     initialize_serial_port();
+    let boot_span = trace_span!("boot");
 
-    if let Some(serial_port) = get_serial_port() {
-        if let Some(frame_buffer) = boot_info.framebuffer.as_mut() {
-            let info = frame_buffer.info().clone();
-            let buffer = frame_buffer.buffer_mut();
-            initialize_framebuffer(buffer, info);
-
-            serial_port.log(format_args!("Frame buffer initialized with resolution {}x{} at {}bpp.",
+    if let Some(frame_buffer) = boot_info.framebuffer.as_mut() {
+        let info = frame_buffer.info().clone();
+        let buffer = frame_buffer.buffer_mut();
+        if initialize_framebuffer(buffer, info) {
+            kinfo!("Frame buffer initialized with resolution {}x{} at {}bpp.",
                 info.width, info.height, info.bytes_per_pixel * 8
-            ), SerialLoggingLevel::Info);
-        } else { panic!("Frame buffer not found!") }
+            );
+        }
+    } else { kwarn!("The bootloader did not pass a frame buffer."); }
+
+    internal::gdt::init();
+    kinfo!("Initialized GDT.");
+
+    internal::timer::init(internal::timer::TIMER_FREQUENCY);
+    kinfo!("Initialized timer with a frequency of {} Hz.",
+        internal::timer::TIMER_FREQUENCY
+    );
+
+    internal::rng::init();
+    kinfo!("Seeded random number generator.");
+
+    internal::idt::init();
+    kinfo!("Initialized IDT.");
+
+    let physical_memory_offset = match boot_info.physical_memory_offset.into_option() {
+        Some(offset) => offset,
+        None => fail_init(KernelInitError::PhysicalMemoryNotMapped)
+    };
+    let phys_mem_offset = VirtAddr::new(physical_memory_offset);
+    let mut mapper = unsafe { internal::memory::init(phys_mem_offset) };
+
+    #[cfg(feature = "memory-map")]
+    internal::memory::log_memory_map(&boot_info.memory_regions);
+
+    #[cfg(feature = "ram-test")]
+    unsafe { internal::memory::run_ram_test(&boot_info.memory_regions, phys_mem_offset); }
+
+    let mut simple_frame_allocator = unsafe {
+        SimpleBootInfoFrameAllocator::new(&boot_info.memory_regions)
+    };
+    {
+        let _span = trace_span!("init_initial_heap");
+        if let Err(error) = internal::allocator::init_initial_heap(&mut mapper, &mut simple_frame_allocator) {
+            fail_init(KernelInitError::InitialHeap(error));
+        }
+    }
 
-        internal::gdt::init();
-        serial_port.log(format_args!("Initialized GDT."), SerialLoggingLevel::Info);
+    kinfo!("Initialized initial heap with {} bytes.",
+        internal::allocator::INITIAL_HEAP_SIZE
+    );
+
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::new(&boot_info.memory_regions)
+    };
+    {
+        let _span = trace_span!("init_main_heap");
+        let result = if cfg!(feature = "lazy-heap") {
+            internal::allocator::init_main_heap_lazy(mapper, frame_allocator)
+        } else {
+            internal::allocator::init_main_heap(&mut mapper, &mut frame_allocator)
+        };
+        if let Err(error) = result {
+            fail_init(KernelInitError::MainHeap(error));
+        }
+        internal::allocator::init_allocator();
+    }
 
-        internal::idt::init();
-        serial_port.log(format_args!("Initialized IDT."), SerialLoggingLevel::Info);
+    kinfo!("Initialized main heap with {} bytes.",
+        internal::allocator::HEAP_SIZE
+    );
 
-        let physical_memory_offset = boot_info.physical_memory_offset.as_ref()
-            .expect("Physical memory offset not found!");
-        let phys_mem_offset = VirtAddr::new(*physical_memory_offset);
-        let mut mapper = unsafe { internal::memory::init(phys_mem_offset) };
+    let mut display_manager = if let Some(frame_buffer) = take_framebuffer() {
+        if let Some(frame_buffer_info) = get_framebuffer_info() {
+            #[cfg(feature = "bench")]
+            systems::bench::compare_displays(frame_buffer, frame_buffer_info);
 
-        let mut simple_frame_allocator = unsafe {
-            SimpleBootInfoFrameAllocator::new(&boot_info.memory_regions)
-        };
-        if let Err(_) = internal::allocator::init_initial_heap(&mut mapper, &mut simple_frame_allocator) {
-            panic!("Initial heap initialization failed!");
-        }
+            #[cfg(feature = "render-check")]
+            if !systems::render_check::run(frame_buffer_info) && cfg!(feature = "qemu-test") {
+                internal::qemu_exit::exit_qemu(internal::qemu_exit::QemuExitCode::Failed);
+            }
 
-        serial_port.log(format_args!("Initialized initial heap with {} bytes.",
-            internal::allocator::INITIAL_HEAP_SIZE
-        ), SerialLoggingLevel::Info);
+            DisplayManager::new(DisplayType::Buffered, frame_buffer, frame_buffer_info)
+        } else { fail_init(KernelInitError::FrameBufferInfoMissing) }
+    } else { headless_display_manager() };
 
-        let mut frame_allocator = unsafe {
-            BootInfoFrameAllocator::new(&boot_info.memory_regions)
-        };
-        if let Err(_) = internal::allocator::init_main_heap(&mut mapper, &mut frame_allocator) {
-            panic!("Heap initialization failed!");
+    display_manager.set_mode(DisplayMode::Dummy);
+    display_manager.clear_screen();
+
+    kinfo!("Display manager initialized using display mode {} and type {}.",
+        display_manager.get_display_mode(), display_manager.get_display_type()
+    );
+
+    let mut kernel = Kernel::new(display_manager);
+
+    kernel.init();
+    drop(boot_span);
+
+    if cfg!(feature = "qemu-test") && !cfg!(feature = "input-replay") {
+        internal::qemu_exit::exit_qemu(internal::qemu_exit::QemuExitCode::Success);
+    }
+
+    let mut scheduler = TickScheduler::new(KERNEL_TICK_INTERVAL_MS, internal::timer::uptime_ms());
+    loop {
+        match kernel.state() {
+            KernelState::Running => {
+                let uptime_ms = internal::timer::uptime_ms();
+                if let Some(elapsed_ms) = scheduler.poll(uptime_ms) {
+                    internal::timer::run_due_timers();
+                    kernel.tick(uptime_ms, elapsed_ms);
+                } else { x86_64::instructions::hlt(); }
+            }, KernelState::Paused => x86_64::instructions::hlt(),
+            KernelState::ShuttingDown { .. } | KernelState::Halted => break
         }
-        internal::allocator::init_allocator();
+    }
+
+    kernel.halt();
+}
+
+/// Logs which step of the boot sequence failed and why, then panics with the same message.
+fn fail_init(error: KernelInitError) -> ! {
+    kerror!("Kernel initialization failed: {:?}", error);
+    panic!("{}", error);
+}
 
-        serial_port.log(format_args!("Initialized main heap with {} bytes.",
-            internal::allocator::HEAP_SIZE
-        ), SerialLoggingLevel::Info);
-
-        if let Some(frame_buffer) = get_framebuffer() {
-            if let Some(frame_buffer_info) = get_framebuffer_info() {
-                let mut display_manager = DisplayManager::new(DisplayType::Buffered, frame_buffer, frame_buffer_info);
-                display_manager.set_mode(DisplayMode::Dummy);
-                display_manager.clear_screen();
-
-                serial_port.log(format_args!("Display manager initialized using display mode {} and type {}.",
-                    display_manager.get_display_mode(), display_manager.get_display_type()
-                ), SerialLoggingLevel::Info);
-
-                let mut kernel = Kernel::new(
-                    display_manager,
-                    serial_port
-                );
-
-                kernel.init();
-
-                let mut tick = 0u64;
-                while kernel.running {
-                    kernel.tick(tick);
-                    tick += 1;
-                }
-
-                kernel.halt();
-            } else { panic!("Frame buffer info not found!") }
-        } else { panic!("Frame buffer not found!") }
-    } else { panic!("Serial port not found!") }
+/// The layout of the frame buffer in memory used when there is no usable one: 80 by 25 cells of the
+/// default bold text font in grayscale, a little over 300 KiB.
+const HEADLESS_FRAME_BUFFER_INFO: FrameBufferInfo = FrameBufferInfo {
+    byte_len: 720 * 450, width: 720, height: 450,
+    pixel_format: PixelFormat::U8, bytes_per_pixel: 1, stride: 720
+};
+
+/// Creates a display manager drawing into a frame buffer in memory when there is no usable frame buffer,
+/// so the kernel loop keeps running without a display. Logging over serial keeps working.
+fn headless_display_manager() -> DisplayManager<'static> {
+    kwarn!("No usable frame buffer, the kernel is running headless.");
+    let frame_buffer = alloc::boxed::Box::leak(alloc::vec![0u8; HEADLESS_FRAME_BUFFER_INFO.byte_len].into_boxed_slice());
+    DisplayManager::new(DisplayType::Simple, frame_buffer, HEADLESS_FRAME_BUFFER_INFO)
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    if let Some(frame_buffer) = get_framebuffer() {
+    if !internal::allocator::is_initialized() { early_panic(info); }
+
+    // SAFETY: The kernel never resumes after a panic, so the previous owner of the frame buffer is gone.
+    // A panic while drawing the panic message must not steal the frame buffer a second time.
+    let frame_buffer = if PANICKING.swap(true, Ordering::SeqCst) { None } else { unsafe { steal_framebuffer() } };
+    if let Some(frame_buffer) = frame_buffer {
         if let Some(frame_buffer_info) = get_framebuffer_info() {
             let mut display_manager = DisplayManager::new(DisplayType::Simple, frame_buffer, frame_buffer_info);
             display_manager.set_mode(DisplayMode::Dummy);
@@ -146,7 +221,7 @@ fn panic(info: &PanicInfo) -> ! {
             }
         }
     }
-    if let Some(serial_port) = get_serial_port() {
+    if let Some(mut serial_port) = SERIAL_PORT.get().and_then(|serial_port| serial_port.try_lock()) {
         if let Some(payload) = info.payload().downcast_ref::<&str>() {
             serial_port.log(format_args!("{}", payload), SerialLoggingLevel::Panic);
         } else if let Some(payload) = info.payload().downcast_ref::<String>() {
@@ -156,48 +231,106 @@ fn panic(info: &PanicInfo) -> ! {
                 serial_port.log(format_args!("{}", message_str), SerialLoggingLevel::Panic);
             }
         }
+        internal::backtrace::log_snapshot(&mut serial_port);
+    }
+    if cfg!(feature = "qemu-test") {
+        internal::qemu_exit::exit_qemu(internal::qemu_exit::QemuExitCode::Failed);
+    }
+    loop {}
+}
+
+/// Reports a panic that happened before the heap was fully initialized. The heap may be missing or broken,
+/// so only a static message and the formatted panic message are written to the serial port and nothing is drawn.
+fn early_panic(info: &PanicInfo) -> ! {
+    if let Some(mut serial_port) = SERIAL_PORT.get().and_then(|serial_port| serial_port.try_lock()) {
+        serial_port.log(format_args!("Kernel panicked before the heap was initialized!"), SerialLoggingLevel::Panic);
+        if let Some(message) = info.message() {
+            serial_port.log(format_args!("{}", message), SerialLoggingLevel::Panic);
+        }
+        if let Some(location) = info.location() {
+            serial_port.log(format_args!("at {}", location), SerialLoggingLevel::Panic);
+        }
+    }
+    if cfg!(feature = "qemu-test") {
+        internal::qemu_exit::exit_qemu(internal::qemu_exit::QemuExitCode::Failed);
     }
     loop {}
 }
 
 // ------- Internal Static Access To Framebuffer ---------
+//
+// The frame buffer is handed out as `&'static mut [u8]` exactly once by `take_framebuffer`.
+// The only other way to get it is `steal_framebuffer`, which is reserved for the first panic,
+// after which the kernel never touches its own reference again.
+
+struct FrameBuffer {
+    start: *mut u8,
+    len: usize,
+    info: FrameBufferInfo
+} unsafe impl Send for FrameBuffer {} unsafe impl Sync for FrameBuffer {}
+
+static FRAMEBUFFER: Once<FrameBuffer> = Once::new();
+static FRAMEBUFFER_TAKEN: AtomicBool = AtomicBool::new(false);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// How often the main loop ticks the kernel. Between ticks the CPU halts until the next interrupt.
+const KERNEL_TICK_INTERVAL_MS: u64 = 10;
+
+/// The serial port the global logger writes to and receives shell input from.
+/// Other ports can get their own [`SerialPortLogger`].
+const LOG_SERIAL_PORT: SerialPort = SerialPort::Com1;
+static SERIAL_PORT: Once<Mutex<SerialPortLogger>> = Once::new();
+
+/// Stores the frame buffer if its length fits its layout and returns whether it did.
+/// Otherwise it is not stored and the kernel runs headless.
+fn initialize_framebuffer(fb: &'static mut [u8], info: FrameBufferInfo) -> bool {
+    if !systems::display::is_frame_buffer_consistent(fb.len(), info) {
+        kerror!("Frame buffer of {} bytes does not fit {} rows of {} pixels with {} bytes each! Running headless.",
+            fb.len(), info.height, info.stride, info.bytes_per_pixel
+        );
+        return false;
+    }
 
-static mut FRAMEBUFFER: Option<&'static mut [u8]> = None;
-static mut FRAMEBUFFER_INFO: Option<FrameBufferInfo> = None;
-static FRAMEBUFFER_INITIALIZED: AtomicBool = AtomicBool::new(false);
+    FRAMEBUFFER.call_once(|| FrameBuffer { start: fb.as_mut_ptr(), len: fb.len(), info });
+    true
+}
 
-static mut SERIAL_PORT: Option<SerialPortLogger> = None;
+/// Hands out the frame buffer. Only the first call gets it, so there is never more than one mutable reference to it.
+fn take_framebuffer() -> Option<&'static mut [u8]> {
+    let frame_buffer = FRAMEBUFFER.get()?;
+    if FRAMEBUFFER_TAKEN.swap(true, Ordering::SeqCst) { return None; }
 
-fn initialize_framebuffer(fb: &'static mut [u8], info: FrameBufferInfo) {
-    unsafe {
-        FRAMEBUFFER = Some(fb);
-        FRAMEBUFFER_INFO = Some(info);
-    }
-    FRAMEBUFFER_INITIALIZED.store(true, Ordering::SeqCst);
+    Some(unsafe { core::slice::from_raw_parts_mut(frame_buffer.start, frame_buffer.len) })
 }
 
-fn get_framebuffer() -> Option<&'static mut [u8]> {
-    if FRAMEBUFFER_INITIALIZED.load(Ordering::SeqCst) {
-        unsafe { FRAMEBUFFER.as_mut().map(|fb| &mut **fb) }
-    } else {
-        None
-    }
+/// Returns the frame buffer even if it was already taken.
+///
+/// # Safety
+/// Only meant for the panic handler. The previous owner of the frame buffer must never use it again.
+unsafe fn steal_framebuffer() -> Option<&'static mut [u8]> {
+    FRAMEBUFFER.get().map(|frame_buffer| unsafe {
+        core::slice::from_raw_parts_mut(frame_buffer.start, frame_buffer.len)
+    })
 }
 
 fn get_framebuffer_info() -> Option<FrameBufferInfo> {
-    if FRAMEBUFFER_INITIALIZED.load(Ordering::SeqCst) {
-        unsafe { FRAMEBUFFER_INFO.clone() }
-    } else {
-        None
-    }
+    FRAMEBUFFER.get().map(|frame_buffer| frame_buffer.info)
 }
 
 fn initialize_serial_port() {
-    unsafe {
-        SERIAL_PORT = Some(SerialPortLogger::init());
-    }
+    SERIAL_PORT.call_once(|| Mutex::new(unsafe { SerialPortLogger::init_port(LOG_SERIAL_PORT) }));
+}
+
+/// Runs the given function with exclusive access to the serial port, returning None if it is not initialized.
+/// Interrupts are disabled in the meantime, so interrupt handlers using the serial port cannot deadlock.
+fn with_serial_port<R>(f: impl FnOnce(&mut SerialPortLogger) -> R) -> Option<R> {
+    let serial_port = SERIAL_PORT.get()?;
+    Some(internal::latency::without_interrupts(|| f(&mut serial_port.lock())))
 }
 
-fn get_serial_port() -> Option<&'static mut SerialPortLogger> {
-    unsafe { SERIAL_PORT.as_mut() }
+/// Runs the given function with the serial port like [`with_serial_port`], but returns None instead of waiting
+/// if the serial port is already locked. Meant for exception handlers, which may have interrupted the lock holder.
+fn try_with_serial_port<R>(f: impl FnOnce(&mut SerialPortLogger) -> R) -> Option<R> {
+    let mut serial_port = SERIAL_PORT.get()?.try_lock()?;
+    Some(f(&mut serial_port))
 }
\ No newline at end of file
@@ -1,12 +1,14 @@
 use alloc::fmt;
+use core::arch::x86_64::_rdtsc;
 use alloc::rc::Rc;
 use core::cell::RefCell;
+use core::sync::atomic::{fence, Ordering};
 
 use bootloader_api::info::FrameBufferInfo;
 
-use crate::api::display::{Colors, DisplayApi, Fonts};
+use crate::api::display::{Colors, DisplayApi, Fonts, Size};
 use crate::drivers::display::{CommonDisplayDriver, DisplayDriverManager, DisplayDriverType, DummyDisplayDriver};
-use crate::drivers::display::text::{TextDisplayDriver, TextDisplayDriverArgs};
+use crate::drivers::display::text::{BackgroundLayer, TextDisplayDriver, TextDisplayDriverArgs, TextDisplayDriverState};
 use crate::systems::display::{BufferedDisplay, SimpleDisplay};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -67,33 +69,98 @@ pub enum DisplayType {
     }
 }
 
+/// Number of frames the draw statistics are computed over.
+const DRAW_STATS_FRAMES: usize = 32;
+
+/// Draw durations of the most recent frames, in CPU cycles read from the time stamp counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DrawStats {
+    pub last: u64,
+    pub min: u64,
+    pub max: u64,
+    pub average: u64,
+    pub frames: usize
+}
+
+/// Keeps the last `N` durations in a ring so statistics can be computed over them.
+struct DurationHistory<const N: usize> {
+    durations: [u64; N],
+    next: usize,
+    len: usize
+} impl<const N: usize> DurationHistory<N> {
+    const fn new() -> Self {
+        Self { durations: [0; N], next: 0, len: 0 }
+    }
+
+    fn push(&mut self, duration: u64) {
+        self.durations[self.next] = duration;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    fn stats(&self) -> DrawStats {
+        if self.len == 0 { return DrawStats::default(); }
+
+        let durations = &self.durations[..self.len];
+        DrawStats {
+            last: self.durations[(self.next + N - 1) % N],
+            min: durations.iter().copied().min().unwrap_or(0),
+            max: durations.iter().copied().max().unwrap_or(0),
+            average: durations.iter().sum::<u64>() / self.len as u64,
+            frames: self.len
+        }
+    }
+}
+
+pub struct DisplayState {
+    display_mode: DisplayMode,
+    text_state: Option<TextDisplayDriverState>
+}
+
 pub struct DisplayManager<'a> {
     display: Rc<RefCell<dyn DisplayApi + 'a>>,
     display_type: DisplayType,
-    driver_manager: DisplayDriverManager<'a>
+    driver_manager: DisplayDriverManager<'a>,
+    draw_history: DurationHistory<DRAW_STATS_FRAMES>
 } #[allow(dead_code)] impl<'a> DisplayManager<'a> {
     /// Creates a new display manager. Be careful as multiple display managers will overwrite each other.
     pub fn new(display_type: DisplayType, frame_buffer: &'a mut [u8], frame_buffer_info: FrameBufferInfo) -> Self {
         let display = display_type.new(frame_buffer, frame_buffer_info);
         let driver_manager = DisplayDriverManager::new();
 
-        Self { display, display_type, driver_manager }
+        Self { display, display_type, driver_manager, draw_history: DurationHistory::new() }
+    }
+
+    /// Replaces the display with one for a new frame buffer, e.g. after a resolution change.
+    /// The current driver is moved over to the new display and repaints the whole screen right away.
+    /// The whole frame buffer gets presented, as nothing of the old frame buffer's content carries over.
+    pub fn reinit(&mut self, frame_buffer: &'a mut [u8], frame_buffer_info: FrameBufferInfo) {
+        let display = self.display_type.new(frame_buffer, frame_buffer_info);
+        self.driver_manager.reactivate(display.clone());
+        self.display = display;
+
+        self.driver_manager.draw_all_complete();
+        self.display.borrow_mut().swap_all();
     }
 
     /// Sets the display mode. This will in turn also set the driver for the display.
+    /// Text mode works on both display types. On a simple display the text driver draws straight to the
+    /// frame buffer, which saves the memory of the back buffer but can make redraws visibly tear.
     pub fn set_mode(&mut self, display_mode: DisplayMode) {
         let driver = display_mode.get_driver(self.display.borrow().get_info());
+        self.driver_manager.set_driver(driver, self.display.clone());
+    }
 
-        match driver {
-            DisplayDriverType::Text(..) => {
-                let display_type = &self.display_type;
-                if display_type != &DisplayType::Buffered {
-                    panic!("Text mode is only supported with buffered display!");
-                }
-            }, _ => {}
-        }
+    /// Puts a driver for the given display mode on top of the current one, e.g. a text HUD over graphics.
+    /// Text drawn with a transparent background lets the layers below show through.
+    pub fn push_mode(&mut self, display_mode: DisplayMode) {
+        let driver = display_mode.get_driver(self.display.borrow().get_info());
+        self.driver_manager.push_driver(driver, self.display.clone());
+    }
 
-        self.driver_manager.set_driver(driver, self.display.clone());
+    /// Removes the topmost driver pushed with [`DisplayManager::push_mode`]. Returns whether there was one.
+    pub fn pop_mode(&mut self) -> bool {
+        self.driver_manager.pop_driver().is_some()
     }
 
     /// Returns the current driver type, which can be used to get the actual driver.
@@ -107,12 +174,61 @@ pub struct DisplayManager<'a> {
     }
 
     /// Returns the current display mode.
-    /// Corresponds directly to the current driver type.
+    /// Corresponds directly to the current driver type, text mode reports the font the text driver was initialized with.
     pub fn get_display_mode(&self) -> DisplayMode {
         match &self.driver_manager.current_driver {
             DisplayDriverType::Unknown => DisplayMode::Unknown,
             DisplayDriverType::Dummy(..) => DisplayMode::Dummy,
-            DisplayDriverType::Text(..) => DisplayMode::Text(Fonts::default())
+            DisplayDriverType::Text(driver, ..) => DisplayMode::Text(driver.get_font().unwrap_or_default())
+        }
+    }
+
+    /// Returns the character size of the active text font, or `None` if the display is not in text mode.
+    pub fn char_size(&self) -> Option<Size> {
+        match &self.driver_manager.current_driver {
+            DisplayDriverType::Text(driver, ..) => driver.get_font().map(|font| font.get_size()),
+            _ => None
+        }
+    }
+
+    /// Returns the size of the text console in columns (width) and rows (height),
+    /// or `None` if the display is not in text mode.
+    pub fn console_size(&self) -> Option<Size> {
+        match &self.driver_manager.current_driver {
+            DisplayDriverType::Text(driver, ..) => Some(driver.get_viewport().size),
+            _ => None
+        }
+    }
+
+    /// Composites the text driver on top of a graphics layer rendered by the given function, or removes it again.
+    /// Has no effect outside of text mode.
+    pub fn set_background_layer(&mut self, background_layer: Option<BackgroundLayer>) {
+        if let DisplayDriverType::Text(driver, ..) = &mut self.driver_manager.current_driver {
+            driver.set_background_layer(background_layer);
+        }
+    }
+
+    /// Saves the current display mode and the contents of the current driver so they can be restored later.
+    pub fn save_state(&self) -> DisplayState {
+        match &self.driver_manager.current_driver {
+            DisplayDriverType::Text(driver, ..) => DisplayState {
+                display_mode: DisplayMode::Text(driver.get_font().unwrap_or_default()),
+                text_state: Some(driver.save_state())
+            }, _ => DisplayState {
+                display_mode: self.get_display_mode(),
+                text_state: None
+            }
+        }
+    }
+
+    /// Restores a previously saved display state. This will in turn also set the display mode.
+    pub fn restore_state(&mut self, state: DisplayState) {
+        self.set_mode(state.display_mode);
+
+        match (&mut self.driver_manager.current_driver, state.text_state) {
+            (DisplayDriverType::Text(driver, ..), Some(text_state)) => {
+                driver.restore_state(text_state);
+            }, _ => {}
         }
     }
 
@@ -122,7 +238,30 @@ pub struct DisplayManager<'a> {
     }
 
     /// Draws all the changes to the screen using the current driver.
+    /// The time it takes gets recorded for [`DisplayManager::draw_stats`].
     pub fn draw_all(&mut self) {
-        self.driver_manager.draw_all()
+        let start = unsafe { _rdtsc() };
+        self.driver_manager.draw_all();
+        self.draw_history.push(unsafe { _rdtsc() }.wrapping_sub(start));
+    }
+
+    /// Renders all pending changes and presents them right away, returning only once the frame buffer shows them.
+    /// Unlike [`DisplayManager::draw_all`], which the kernel calls once per tick, this is meant to be called
+    /// before the kernel does something blocking, so output written just before is guaranteed to be visible.
+    /// Text drivers ignore their render budget here, so nothing is left for later frames. The drivers already
+    /// present their frame at the end of drawing, so only the memory fence is added on top.
+    pub fn flush(&mut self) {
+        self.driver_manager.draw_all_complete();
+        fence(Ordering::SeqCst);
+    }
+
+    /// Returns how long the last call to `draw_all` took, in CPU cycles read from the time stamp counter.
+    pub fn last_draw_cycles(&self) -> u64 {
+        self.draw_history.stats().last
+    }
+
+    /// Returns the minimum, maximum and average duration of the most recent calls to `draw_all`.
+    pub fn draw_stats(&self) -> DrawStats {
+        self.draw_history.stats()
     }
 }
\ No newline at end of file
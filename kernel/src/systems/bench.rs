@@ -0,0 +1,85 @@
+use alloc::format;
+use bootloader_api::info::FrameBufferInfo;
+use core::arch::x86_64::_rdtsc;
+use crate::api::display::{Colors, DisplayApi, Fonts, Position, TextAlignment, TextBaseline, TextLineHeight};
+use crate::internal::serial::SerialLoggingLevel;
+use crate::systems::display::{BufferedDisplay, SimpleDisplay};
+
+/// How often a screenful of text gets scrolled by one line in the benchmark workload.
+const SCROLL_ITERATIONS: usize = 25;
+
+/// Result of running the benchmark workload on a display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchResult {
+    pub cycles: u64,
+    pub pixels: usize,
+    pub checksum: u64
+}
+
+/// Renders the same text workload on a simple and a buffered display and logs how long each took,
+/// how many pixels were drawn and whether both left the same content in the frame buffer.
+pub fn compare_displays(frame_buffer: &mut [u8], frame_buffer_info: FrameBufferInfo) {
+    let simple = run_workload(&mut SimpleDisplay::new(frame_buffer, frame_buffer_info));
+    let simple_checksum = checksum(frame_buffer);
+
+    let buffered = run_workload(&mut BufferedDisplay::new(frame_buffer, frame_buffer_info));
+    let buffered_checksum = checksum(frame_buffer);
+
+    let results = [
+        ("Simple", BenchResult { checksum: simple_checksum, ..simple }),
+        ("Buffered", BenchResult { checksum: buffered_checksum, ..buffered })
+    ];
+
+    crate::with_serial_port(|serial_logger| {
+        for (name, result) in results.iter() {
+            serial_logger.log(format_args!("{} display: {} cycles, {} pixels drawn, checksum {:#018x}.",
+                name, result.cycles, result.pixels, result.checksum
+            ), SerialLoggingLevel::Info);
+        }
+        serial_logger.log(format_args!("Simple and buffered display output {}.",
+            if simple_checksum == buffered_checksum { "matches" } else { "differs" }
+        ), if simple_checksum == buffered_checksum { SerialLoggingLevel::Info } else { SerialLoggingLevel::Warning });
+    });
+}
+
+/// Scrolls a screenful of text by redrawing every line for each scroll step, swapping after each step.
+fn run_workload(display: &mut dyn DisplayApi) -> BenchResult {
+    let info = display.get_info();
+    let font = Fonts::default();
+    let char_size = font.get_size();
+    let columns = info.width / char_size.width;
+    let rows = info.height / char_size.height;
+
+    let mut pixels = 0;
+    let start = unsafe { _rdtsc() };
+
+    display.clear(Colors::Black.into());
+    display.swap();
+
+    for iteration in 0..SCROLL_ITERATIONS {
+        for row in 0..rows {
+            let mut line = format!("Line {} of the benchmark workload.", iteration + row);
+            line.truncate(columns);
+
+            display.draw_text(
+                &line, Position::new(0, row * char_size.height),
+                Colors::White.into(), Some(Colors::Black.into()),
+                font.into(), false, false,
+                TextBaseline::Top, TextAlignment::Left, TextLineHeight::Full, 1
+            );
+            pixels += line.len() * char_size.width * char_size.height;
+        }
+        display.swap();
+    }
+
+    let cycles = unsafe { _rdtsc() } - start;
+
+    BenchResult { cycles, pixels, checksum: 0 }
+}
+
+/// FNV-1a hash of the frame buffer contents.
+fn checksum(frame_buffer: &[u8]) -> u64 {
+    frame_buffer.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
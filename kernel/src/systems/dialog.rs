@@ -0,0 +1,153 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::api::display::{Position, Region, Size};
+use crate::drivers::display::text::{BoxStyle, CursorState, RegionSnapshot, TextDisplayDriver};
+use crate::drivers::display::wrap_text;
+
+/// What happened after a key was handed to an open [`Dialog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogResult {
+    /// The dialog is still open.
+    Open,
+    /// The button with the given index was confirmed with enter. The dialog got closed.
+    Confirmed(usize),
+    /// The dialog was cancelled with escape. The dialog got closed.
+    Cancelled
+}
+
+/// A modal dialog with a message and a row of buttons, drawn centered over the text display.
+/// The cells it covers are captured when it opens and put back when it closes, so the content below is unchanged.
+pub struct Dialog {
+    message: String,
+    buttons: Vec<String>,
+    selected: usize,
+    style: BoxStyle,
+    covered: Option<(RegionSnapshot, CursorState)>
+} #[allow(dead_code)] impl Dialog {
+    pub fn new(message: &str, buttons: &[&str]) -> Self {
+        if buttons.is_empty() { panic!("A dialog needs at least one button!"); }
+
+        Self {
+            message: String::from(message),
+            buttons: buttons.iter().map(|button| String::from(*button)).collect(),
+            selected: 0,
+            style: BoxStyle::Single,
+            covered: None
+        }
+    }
+
+    /// Sets the lines of the border. The font needs to contain the box drawing glyphs.
+    pub fn with_style(mut self, style: BoxStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Returns whether the dialog is currently shown.
+    pub fn is_open(&self) -> bool {
+        self.covered.is_some()
+    }
+
+    /// Returns the index of the currently selected button.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Captures the cells the dialog covers and draws it. Does nothing if it is already open.
+    pub fn open(&mut self, driver: &mut TextDisplayDriver) {
+        if self.is_open() { return; }
+
+        let region = self.region(driver);
+        self.covered = Some((driver.capture_region(region), driver.cursor_state()));
+        self.draw(driver);
+    }
+
+    /// Puts back the cells and the cursor the dialog covered. Does nothing if it is not open.
+    pub fn close(&mut self, driver: &mut TextDisplayDriver) {
+        if let Some((snapshot, cursor_state)) = self.covered.take() {
+            driver.restore_region(&snapshot);
+            driver.set_cursor_state(cursor_state);
+        }
+    }
+
+    /// Handles a key while the dialog is open. Tab and `l` select the next button, `h` the previous one,
+    /// enter confirms the selected button and escape cancels. Confirming or cancelling closes the dialog.
+    pub fn handle_key(&mut self, key: char, driver: &mut TextDisplayDriver) -> DialogResult {
+        if !self.is_open() { return DialogResult::Cancelled; }
+
+        match key {
+            '\t' | 'l' => self.selected = (self.selected + 1) % self.buttons.len(),
+            'h' => self.selected = (self.selected + self.buttons.len() - 1) % self.buttons.len(),
+            '\n' | '\r' => {
+                self.close(driver);
+                return DialogResult::Confirmed(self.selected);
+            }, '\x1B' => {
+                self.close(driver);
+                return DialogResult::Cancelled;
+            }, _ => return DialogResult::Open
+        }
+
+        self.draw(driver);
+        DialogResult::Open
+    }
+
+    /// Returns the region the dialog covers, centered in the viewport: the border, the wrapped message,
+    /// an empty row and the buttons, with one column of padding on each side.
+    fn region(&self, driver: &TextDisplayDriver) -> Region {
+        let viewport = driver.get_viewport().size;
+        let max_inner = viewport.width.saturating_sub(4).max(1);
+
+        let lines = wrap_text(&self.message, max_inner);
+        let content_width = lines.iter().map(|line| line.chars().count())
+            .chain(core::iter::once(self.buttons_width()))
+            .max().unwrap_or(0)
+            .min(max_inner);
+        let size = Size::new(
+            (content_width + 4).min(viewport.width),
+            (lines.len() + 4).min(viewport.height)
+        );
+
+        Region::new(Position::new(
+            (viewport.width - size.width) / 2,
+            (viewport.height - size.height) / 2
+        ), size)
+    }
+
+    /// Returns the width of the button row, e.g. `[ Yes ] [ No ]`.
+    fn buttons_width(&self) -> usize {
+        self.buttons.iter().map(|button| button.chars().count() + 4).sum::<usize>() + self.buttons.len() - 1
+    }
+
+    fn draw(&self, driver: &mut TextDisplayDriver) {
+        let region = self.region(driver);
+        let Region { position, size } = region;
+        let inner_width = size.width.saturating_sub(4);
+        let cursor_state = driver.cursor_state();
+
+        driver.fill_region(region, ' ');
+        driver.draw_box(region, self.style);
+
+        for (row, line) in wrap_text(&self.message, inner_width.max(1)).iter().enumerate() {
+            if row + 4 > size.height { break; }
+            driver.write_string_at(line, Position::new(position.x + 2, position.y + 1 + row), false);
+        }
+
+        // Viewports too small for a border, a message row and the button row leave the buttons out.
+        if size.height < 4 { return; }
+
+        let buttons_y = position.y + size.height - 2;
+        let mut x = position.x + 2 + inner_width.saturating_sub(self.buttons_width()) / 2;
+        for (index, button) in self.buttons.iter().enumerate() {
+            if index == self.selected {
+                driver.set_text_color(cursor_state.background_color);
+                driver.set_background_color(cursor_state.text_color);
+            }
+            driver.write_string_at(&format!("[ {} ]", button), Position::new(x, buttons_y), false);
+            driver.set_text_color(cursor_state.text_color);
+            driver.set_background_color(cursor_state.background_color);
+
+            x += button.chars().count() + 5;
+        }
+    }
+}
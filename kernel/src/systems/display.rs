@@ -1,6 +1,7 @@
 use alloc::string::ToString;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt::Write;
 use bootloader_api::info::{FrameBufferInfo, PixelFormat};
 use embedded_graphics::draw_target::DrawTarget;
 use embedded_graphics::geometry::{Dimensions, Point};
@@ -10,17 +11,35 @@ use embedded_graphics::pixelcolor::{Rgb888, RgbColor};
 use embedded_graphics::primitives::Rectangle;
 use embedded_graphics::text::{DecorationColor, Text, TextStyle};
 use embedded_graphics::text::renderer::CharacterStyle;
-use crate::api::display::{Color, DisplayApi, Position, TextAlignment, TextBaseline, TextLineHeight};
+use crate::api::display::{Color, DisplayApi, Palette, Position, Region, Size, TextAlignment, TextBaseline, TextLineHeight};
+use crate::internal::base64;
+use crate::internal::serial::SerialLoggingLevel;
+
+/// Returned when a pixel position lies outside the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub position: Position,
+    pub size: Size
+}
 
 trait DisplayContext {
     fn swap(&mut self);
 }
 
 pub struct SimpleDisplay<'a> {
-    context: SimpleDisplayContext<'a>
-} impl<'a> SimpleDisplay<'a> {
+    context: SimpleDisplayContext<'a>,
+    #[allow(dead_code)]
+    palette: Palette
+} #[allow(dead_code)] impl<'a> SimpleDisplay<'a> {
     pub fn new(frame_buffer: &'a mut [u8], frame_buffer_info: FrameBufferInfo) -> Self {
-        Self { context: SimpleDisplayContext::new(frame_buffer, frame_buffer_info) }
+        Self { context: SimpleDisplayContext::new(frame_buffer, frame_buffer_info), palette: Palette::vga() }
+    }
+
+    /// Sets a single pixel, or returns an error without drawing if the position lies outside the display.
+    pub fn try_set_pixel(&mut self, position: Position, color: Color) -> Result<(), OutOfBounds> {
+        check_bounds(self.context.frame_buffer_info, position)?;
+        self.context.set_pixel(position, color);
+        Ok(())
     }
 } impl DisplayApi for SimpleDisplay<'_> {
     fn draw(&mut self, buffer: &[u8]) {
@@ -37,26 +56,15 @@ pub struct SimpleDisplay<'a> {
         &mut self, character: char, position: Position,
         text_color: Color, background_color: Option<Color>,
         font: MonoFont, underline: bool, strikethrough: bool,
-        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight
+        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
     ) {
-        let mut font_style = MonoTextStyle::new(&font, text_color.into());
-        font_style.background_color = background_color.map(|color| color.into());
-
-        if underline { font_style.set_underline_color(DecorationColor::TextColor); }
-        if strikethrough { font_style.set_strikethrough_color(DecorationColor::TextColor); }
-
-        let mut text_style = TextStyle::default();
-        text_style.baseline = baseline.into();
-        text_style.alignment = alignment.into();
-        text_style.line_height = line_height.into();
-
         let binding = character.to_string();
-        let text = Text::with_text_style(
-            &*binding, Point::new(position.x as i32, position.y as i32),
-            font_style, text_style
-        );
-
-        if let Err(_) = text.draw(&mut self.context) {
+        if let Err(_) = draw_text_on(
+            &mut self.context, &binding, position,
+            text_color, background_color,
+            &font, underline, strikethrough,
+            baseline, alignment, line_height, scale
+        ) {
             panic!("Failed to draw character!")
         }
     }
@@ -65,81 +73,101 @@ pub struct SimpleDisplay<'a> {
         &mut self, text: &str, position: Position,
         text_color: Color, background_color: Option<Color>,
         font: MonoFont, underline: bool, strikethrough: bool,
-        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight
+        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
     ) {
-        let mut font_style = MonoTextStyle::new(&font, text_color.into());
-        font_style.background_color = background_color.map(|color| color.into());
-
-        if underline { font_style.set_underline_color(DecorationColor::TextColor); }
-        if strikethrough { font_style.set_strikethrough_color(DecorationColor::TextColor); }
-
-        let mut text_style = TextStyle::default();
-        text_style.baseline = baseline.into();
-        text_style.alignment = alignment.into();
-        text_style.line_height = line_height.into();
+        if let Err(_) = draw_text_on(
+            &mut self.context, text, position,
+            text_color, background_color,
+            &font, underline, strikethrough,
+            baseline, alignment, line_height, scale
+        ) {
+            panic!("Failed to draw text!")
+        }
+    }
 
-        let text = Text::with_text_style(
-            text, Point::new(position.x as i32, position.y as i32),
-            font_style, text_style
-        );
+    fn draw_image(&mut self, position: Position, size: Size, pixels: &[Color]) {
+        if pixels.len() != size.width * size.height {
+            panic!("Image data does not match the given size!");
+        }
 
-        if let Err(_) = text.draw(&mut self.context) {
-            panic!("Failed to draw text!")
+        let visible = clip_size(self.context.frame_buffer_info, position, size);
+        for y in 0..visible.height {
+            for x in 0..visible.width {
+                self.context.set_pixel(
+                    Position::new(position.x + x, position.y + y),
+                    pixels[y * size.width + x]
+                );
+            }
         }
     }
 
+    fn draw_indexed(&mut self, position: Position, size: Size, indices: &[u8]) {
+        let pixels = self.palette.resolve(indices);
+        self.draw_image(position, size, &pixels);
+    }
+
+    fn set_palette(&mut self, palette: Palette) { self.palette = palette; }
+
     fn clear(&mut self, color: Color) {
-        for byte_offset in (0..self.context.frame_buffer.len()).step_by(self.context.frame_buffer_info.bytes_per_pixel) {
-            set_pixel_in_at(self.context.frame_buffer, self.context.frame_buffer_info, byte_offset, color);
-        }
+        let info = self.context.frame_buffer_info;
+        self.context.fill_region(Region::new(Position::new(0, 0), Size::new(info.width, info.height)), color);
     }
 
     fn swap(&mut self) { self.context.swap(); }
 
+    fn swap_all(&mut self) { self.context.swap(); }
+
+    fn take_redraw_request(&mut self) -> bool { false }
+
     fn get_info(&self) -> FrameBufferInfo { self.context.frame_buffer_info }
+
+    fn snapshot(&self) -> &[u8] { self.context.frame_buffer }
 }
 
 pub struct BufferedDisplay<'a> {
-    context: BufferedDisplayContext<'a>
-} impl<'a> BufferedDisplay<'a> {
+    context: BufferedDisplayContext<'a>,
+    #[allow(dead_code)]
+    palette: Palette
+} #[allow(dead_code)] impl<'a> BufferedDisplay<'a> {
     pub fn new(frame_buffer: &'a mut [u8], frame_buffer_info: FrameBufferInfo) -> Self {
-        Self { context: BufferedDisplayContext::new(frame_buffer, frame_buffer_info) }
+        Self { context: BufferedDisplayContext::new(frame_buffer, frame_buffer_info), palette: Palette::vga() }
+    }
+
+    /// Sets a single pixel, or returns an error without drawing if the position lies outside the display.
+    pub fn try_set_pixel(&mut self, position: Position, color: Color) -> Result<(), OutOfBounds> {
+        check_bounds(self.context.frame_buffer_info, position)?;
+        self.context.set_pixel(position, color);
+        Ok(())
     }
 } impl DisplayApi for BufferedDisplay<'_> {
     fn draw(&mut self, buffer: &[u8]) {
-        if buffer.len() != self.context.back_buffer.len() {
+        if buffer.len() != self.context.frame_buffer.len() {
             panic!("Buffer data does not match the expected size!");
         }
 
-        for (i, byte) in buffer.iter().enumerate() {
-            self.context.back_buffer[i] = *byte;
+        let info = self.context.frame_buffer_info;
+        for y in 0..info.height {
+            for x in 0..info.width {
+                let byte_offset = (y * info.stride + x) * info.bytes_per_pixel;
+                self.context.back_buffer[y * info.width + x] = get_pixel_in_at(buffer, info, byte_offset);
+            }
         }
+        self.context.mark_all_dirty();
     }
 
     fn draw_char(
         &mut self, character: char, position: Position,
         text_color: Color, background_color: Option<Color>,
         font: MonoFont, underline: bool, strikethrough: bool,
-        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight
+        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
     ) {
-        let mut font_style = MonoTextStyle::new(&font, text_color.into());
-        font_style.background_color = background_color.map(|color| color.into());
-
-        if underline { font_style.set_underline_color(DecorationColor::TextColor); }
-        if strikethrough { font_style.set_strikethrough_color(DecorationColor::TextColor); }
-
-        let mut text_style = TextStyle::default();
-        text_style.baseline = baseline.into();
-        text_style.alignment = alignment.into();
-        text_style.line_height = line_height.into();
-
         let binding = character.to_string();
-        let text = Text::with_text_style(
-            &*binding, Point::new(position.x as i32, position.y as i32),
-            font_style, text_style
-        );
-
-        if let Err(_) = text.draw(&mut self.context) {
+        if let Err(_) = draw_text_on(
+            &mut self.context, &binding, position,
+            text_color, background_color,
+            &font, underline, strikethrough,
+            baseline, alignment, line_height, scale
+        ) {
             panic!("Failed to draw character!")
         }
     }
@@ -148,38 +176,64 @@ pub struct BufferedDisplay<'a> {
         &mut self, text: &str, position: Position,
         text_color: Color, background_color: Option<Color>,
         font: MonoFont, underline: bool, strikethrough: bool,
-        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight
+        baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
     ) {
-        let mut font_style = MonoTextStyle::new(&font, text_color.into());
-        font_style.background_color = background_color.map(|color| color.into());
-
-        if underline { font_style.set_underline_color(DecorationColor::TextColor); }
-        if strikethrough { font_style.set_strikethrough_color(DecorationColor::TextColor); }
+        if let Err(_) = draw_text_on(
+            &mut self.context, text, position,
+            text_color, background_color,
+            &font, underline, strikethrough,
+            baseline, alignment, line_height, scale
+        ) {
+            panic!("Failed to draw text!")
+        }
+    }
 
-        let mut text_style = TextStyle::default();
-        text_style.baseline = baseline.into();
-        text_style.alignment = alignment.into();
-        text_style.line_height = line_height.into();
+    fn draw_image(&mut self, position: Position, size: Size, pixels: &[Color]) {
+        if pixels.len() != size.width * size.height {
+            panic!("Image data does not match the given size!");
+        }
 
-        let text = Text::with_text_style(
-            text, Point::new(position.x as i32, position.y as i32),
-            font_style, text_style
-        );
+        let visible = clip_size(self.context.frame_buffer_info, position, size);
+        if visible.width == 0 || visible.height == 0 { return; }
 
-        if let Err(_) = text.draw(&mut self.context) {
-            panic!("Failed to draw text!")
+        for y in 0..visible.height {
+            for x in 0..visible.width {
+                self.context.set_pixel(
+                    Position::new(position.x + x, position.y + y),
+                    pixels[y * size.width + x]
+                );
+            }
         }
+        self.context.mark_dirty(Region::new(position, visible));
+    }
+
+    fn draw_indexed(&mut self, position: Position, size: Size, indices: &[u8]) {
+        let pixels = self.palette.resolve(indices);
+        self.draw_image(position, size, &pixels);
     }
 
+    fn set_palette(&mut self, palette: Palette) { self.palette = palette; }
+
     fn clear(&mut self, color: Color) {
-        for byte_offset in (0..self.context.frame_buffer.len()).step_by(self.context.frame_buffer_info.bytes_per_pixel) {
-            set_pixel_in_at(self.context.back_buffer.as_mut_slice(), self.context.frame_buffer_info, byte_offset, color);
-        }
+        let info = self.context.frame_buffer_info;
+        self.context.fill_region(Region::new(Position::new(0, 0), Size::new(info.width, info.height)), color);
+        self.context.mark_all_dirty();
     }
 
     fn swap(&mut self) { self.context.swap(); }
 
+    fn swap_all(&mut self) {
+        self.context.mark_all_dirty();
+        self.context.swap();
+    }
+
+    fn take_redraw_request(&mut self) -> bool {
+        core::mem::take(&mut self.context.redraw_requested)
+    }
+
     fn get_info(&self) -> FrameBufferInfo { self.context.frame_buffer_info }
+
+    fn snapshot(&self) -> &[u8] { self.context.frame_buffer }
 }
 
 struct SimpleDisplayContext<'a> {
@@ -190,7 +244,10 @@ struct SimpleDisplayContext<'a> {
         Self { frame_buffer, frame_buffer_info }
     }
 
+    /// Sets a pixel of the frame buffer. Positions outside the frame buffer are clipped.
     fn set_pixel(&mut self, position: Position, color: Color) {
+        if check_bounds(self.frame_buffer_info, position).is_err() { return; }
+
         let byte_offset = {
             let line_offset = position.y * self.frame_buffer_info.stride;
             let pixel_offset = line_offset + position.x;
@@ -199,6 +256,41 @@ struct SimpleDisplayContext<'a> {
 
         set_pixel_in_at(self.frame_buffer, self.frame_buffer_info, byte_offset, color);
     }
+
+    /// Fills a region of the frame buffer with a color, clipped to the frame buffer.
+    /// Opaque colors are encoded once and copied along the first scanline and then from scanline to scanline.
+    fn fill_region(&mut self, region: Region, color: Color) {
+        let info = self.frame_buffer_info;
+        let visible = clip_size(info, region.position, region.size);
+        if visible.width == 0 || visible.height == 0 { return; }
+
+        if color.alpha != 255 {
+            for y in 0..visible.height {
+                for x in 0..visible.width {
+                    self.set_pixel(Position::new(region.position.x + x, region.position.y + y), color);
+                }
+            }
+            return;
+        }
+
+        let bytes_per_pixel = info.bytes_per_pixel;
+        let row_length = visible.width * bytes_per_pixel;
+        let first_row = (region.position.y * info.stride + region.position.x) * bytes_per_pixel;
+
+        let pixel = encode_pixel(info, color);
+        self.frame_buffer[first_row..first_row + bytes_per_pixel].copy_from_slice(&pixel[..bytes_per_pixel]);
+        let mut filled = bytes_per_pixel;
+        while filled < row_length {
+            let count = filled.min(row_length - filled);
+            self.frame_buffer.copy_within(first_row..first_row + count, first_row + filled);
+            filled += count;
+        }
+
+        for y in 1..visible.height {
+            let row = first_row + y * info.stride * bytes_per_pixel;
+            self.frame_buffer.copy_within(first_row..first_row + row_length, row);
+        }
+    }
 } impl DisplayContext for SimpleDisplayContext<'_> {
     fn swap(&mut self) {}
 } impl DrawTarget for SimpleDisplayContext<'_> {
@@ -210,6 +302,8 @@ struct SimpleDisplayContext<'a> {
 
         for pixel in pixels.into_iter() {
             let Pixel(point, color) = pixel;
+            if point.x < 0 || point.y < 0 { continue; }
+
             self.set_pixel(Position::new(
                 point.x as usize,
                 point.y as usize
@@ -228,36 +322,142 @@ struct SimpleDisplayContext<'a> {
     }
 }
 
+/// Draws into a back buffer of RGB colors, one per visible pixel without the stride padding.
+/// The colors only get converted to the pixel format of the frame buffer when swapping.
 struct BufferedDisplayContext<'a> {
     frame_buffer: &'a mut [u8],
-    back_buffer: Vec<u8>,
-    frame_buffer_info: FrameBufferInfo
+    back_buffer: Vec<Color>,
+    frame_buffer_info: FrameBufferInfo,
+    dirty_regions: Vec<Region>,
+    all_dirty: bool,
+    redraw_requested: bool
 } impl<'a> BufferedDisplayContext<'a> {
+    /// Creates the context with a back buffer in memory.
+    ///
+    /// Page flipping is not implemented: the bootloader only hands over a linear frame buffer and gives no way
+    /// to make the hardware scan out from a different offset, which would need a driver for the graphics device.
+    /// Room for a second page is only detected and logged, so a flip-based swap can build on it once such a driver exists.
+    /// Until then, swapping always copies the back buffer into the visible page.
     pub fn new(frame_buffer: &'a mut [u8], frame_buffer_info: FrameBufferInfo) -> Self {
-        let back_buffer = vec![0; frame_buffer.len()];
+        let back_buffer = vec![Color::new(0, 0, 0); frame_buffer_info.width * frame_buffer_info.height];
 
-        Self { frame_buffer, back_buffer, frame_buffer_info }
+        if let Some(offset) = second_page_offset(frame_buffer.len(), frame_buffer_info) {
+            crate::with_serial_port(|serial_logger| serial_logger.log(format_args!(
+                "Frame buffer has room for a second page at offset {:#x}, but flipping needs a graphics device driver. Copying on swap instead.",
+                offset
+            ), SerialLoggingLevel::Debug));
+        }
+
+        Self { frame_buffer, back_buffer, frame_buffer_info, dirty_regions: Vec::new(), all_dirty: true, redraw_requested: false }
     }
 
+    /// Shrinks the layout to the rows that fit into the frame buffer and reallocates the back buffer to match.
+    /// The content of the old back buffer is lost, so a redraw gets requested from the drivers.
+    fn recover_size_mismatch(&mut self) {
+        let info = self.frame_buffer_info;
+        let row_size = info.stride * info.bytes_per_pixel;
+        let height = if row_size == 0 { 0 } else { info.height.min(self.frame_buffer.len() / row_size) };
+
+        crate::with_serial_port(|serial_logger| serial_logger.log(format_args!(
+            "Frame buffer of {} bytes does not match its layout of {} bytes or the back buffer of {} pixels! Continuing with {} rows.",
+            self.frame_buffer.len(), frame_size(info), self.back_buffer.len(), height
+        ), SerialLoggingLevel::Error));
+
+        self.frame_buffer_info.height = height;
+        self.frame_buffer_info.byte_len = row_size * height;
+        self.back_buffer = vec![Color::new(0, 0, 0); info.width * height];
+        self.mark_all_dirty();
+        self.redraw_requested = true;
+    }
+
+    /// Makes the next swap copy the whole back buffer instead of only the changed regions.
+    fn mark_all_dirty(&mut self) {
+        self.all_dirty = true;
+        self.dirty_regions.clear();
+    }
+
+    fn mark_dirty(&mut self, region: Region) {
+        if !self.all_dirty {
+            self.dirty_regions.push(region);
+        }
+    }
+
+    /// Sets a pixel of the back buffer. Positions outside the frame buffer are clipped.
     fn set_pixel(&mut self, position: Position, color: Color) {
-        let byte_offset = {
-            let line_offset = position.y * self.frame_buffer_info.stride;
-            let pixel_offset = line_offset + position.x;
-            pixel_offset * self.frame_buffer_info.bytes_per_pixel
-        };
+        if check_bounds(self.frame_buffer_info, position).is_err() { return; }
+
+        let index = position.y * self.frame_buffer_info.width + position.x;
+        self.back_buffer[index] = blend_pixel(color, self.back_buffer[index]);
+    }
+
+    /// Fills a region of the back buffer with a color, clipped to the frame buffer, and marks it as dirty.
+    fn fill_region(&mut self, region: Region, color: Color) {
+        let info = self.frame_buffer_info;
+        let visible = clip_size(info, region.position, region.size);
+        if visible.width == 0 || visible.height == 0 { return; }
+
+        for y in region.position.y..(region.position.y + visible.height) {
+            let start = y * info.width + region.position.x;
+            let row = &mut self.back_buffer[start..start + visible.width];
+            if color.alpha == 255 { row.fill(color); } else {
+                for pixel in row.iter_mut() {
+                    *pixel = blend_pixel(color, *pixel);
+                }
+            }
+        }
 
-        set_pixel_in_at(self.back_buffer.as_mut_slice(), self.frame_buffer_info, byte_offset, color);
+        self.mark_dirty(Region::new(region.position, visible));
+    }
+
+    /// Converts a row of the back buffer to the pixel format of the frame buffer and writes it there.
+    fn present_row(&mut self, y: usize, start_x: usize, width: usize) {
+        let info = self.frame_buffer_info;
+
+        for x in start_x..(start_x + width) {
+            let byte_offset = (y * info.stride + x) * info.bytes_per_pixel;
+            set_pixel_in_at(self.frame_buffer, info, byte_offset, self.back_buffer[y * info.width + x]);
+        }
     }
 } impl DisplayContext for BufferedDisplayContext<'_> {
     fn swap(&mut self) {
-        let frame_buffer_len = self.frame_buffer.len();
-        let back_buffer_len = self.back_buffer.len();
-
-        if frame_buffer_len != back_buffer_len {
-            panic!("Frame buffer and back buffer sizes do not match!");
+        let info = self.frame_buffer_info;
+        if frame_size(info) > self.frame_buffer.len() || self.back_buffer.len() != info.width * info.height {
+            self.recover_size_mismatch();
+        }
+        let info = self.frame_buffer_info;
+
+        if self.all_dirty {
+            for y in 0..info.height {
+                self.present_row(y, 0, info.width);
+            }
+
+            #[cfg(feature = "remote-display")]
+            crate::systems::remote::send_region(
+                Region::new(Position::new(0, 0), Size::new(info.width, info.height)), &self.back_buffer, info.width
+            );
+        } else {
+            let dirty_regions = core::mem::take(&mut self.dirty_regions);
+
+            for region in dirty_regions.iter() {
+                for y in region.position.y..(region.position.y + region.size.height) {
+                    self.present_row(y, region.position.x, region.size.width);
+                }
+
+                #[cfg(feature = "remote-display")]
+                crate::systems::remote::send_region(*region, &self.back_buffer, info.width);
+            }
         }
 
-        self.frame_buffer.copy_from_slice(&self.back_buffer);
+        self.all_dirty = false;
+        self.dirty_regions.clear();
+
+        #[cfg(feature = "verify-swap")]
+        if let Some((position, mismatches)) = find_frame_buffer_mismatch(self.frame_buffer, info, &self.back_buffer) {
+            crate::with_serial_port(|serial_logger| serial_logger.log(format_args!(
+                "Frame buffer diverges from the back buffer after swap in {} pixels, first at {}x{}!",
+                mismatches, position.x, position.y
+            ), SerialLoggingLevel::Warning));
+        }
     }
 } impl DrawTarget for BufferedDisplayContext<'_> {
     type Color = Rgb888;
@@ -266,16 +466,37 @@ struct BufferedDisplayContext<'a> {
     fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
         where I: IntoIterator<Item = Pixel<Self::Color>> {
 
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+
         for pixel in pixels.into_iter() {
             let Pixel(point, color) = pixel;
-            self.set_pixel(Position::new(
+            if point.x < 0 || point.y < 0 { continue; }
+
+            let position = Position::new(
                 point.x as usize,
                 point.y as usize
-            ), Color::new(
+            );
+            if check_bounds(self.frame_buffer_info, position).is_err() { continue; }
+
+            self.set_pixel(position, Color::new(
                 color.r(),
                 color.g(),
                 color.b()
             ));
+
+            bounds = Some(match bounds {
+                Some((min_x, min_y, max_x, max_y)) => (
+                    min_x.min(position.x), min_y.min(position.y),
+                    max_x.max(position.x), max_y.max(position.y)
+                ), None => (position.x, position.y, position.x, position.y)
+            });
+        }
+
+        if let Some((min_x, min_y, max_x, max_y)) = bounds {
+            self.mark_dirty(Region::new(
+                Position::new(min_x, min_y),
+                Size::new(max_x - min_x + 1, max_y - min_y + 1)
+            ));
         }
 
         Ok(())
@@ -286,6 +507,86 @@ struct BufferedDisplayContext<'a> {
     }
 }
 
+/// Forwards pixels to another draw target, expanding every pixel into a square block of `scale` pixels
+/// around the given origin. Pixels that end up outside of the target are dropped.
+struct ScaledTarget<'t, T> {
+    target: &'t mut T,
+    origin: Point,
+    scale: u32
+} impl<T: DrawTarget<Color = Rgb888>> DrawTarget for ScaledTarget<'_, T> {
+    type Color = Rgb888;
+    type Error = T::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where I: IntoIterator<Item = Pixel<Self::Color>> {
+
+        let (origin, scale) = (self.origin, self.scale as i32);
+        let bounds = self.target.bounding_box();
+
+        self.target.draw_iter(pixels.into_iter().flat_map(move |Pixel(point, color)| {
+            let top_left = origin + (point - origin) * scale;
+            (0..scale * scale).map(move |offset| Pixel(top_left + Point::new(offset % scale, offset / scale), color))
+        }).filter(|Pixel(point, _)| bounds.contains(*point)))
+    }
+} impl<T: Dimensions> Dimensions for ScaledTarget<'_, T> {
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+/// Draws text onto a draw target, upscaling each glyph by an integer factor with nearest-neighbor
+/// block expansion around the given position. A scale of 1 draws the font as is.
+fn draw_text_on<T: DrawTarget<Color = Rgb888>>(
+    target: &mut T, text: &str, position: Position,
+    text_color: Color, background_color: Option<Color>,
+    font: &MonoFont, underline: bool, strikethrough: bool,
+    baseline: TextBaseline, alignment: TextAlignment, line_height: TextLineHeight, scale: u32
+) -> Result<(), T::Error> {
+    let mut font_style = MonoTextStyle::new(font, text_color.into());
+    font_style.background_color = background_color.map(|color| color.into());
+
+    if underline { font_style.set_underline_color(DecorationColor::TextColor); }
+    if strikethrough { font_style.set_strikethrough_color(DecorationColor::TextColor); }
+
+    let mut text_style = TextStyle::default();
+    text_style.baseline = baseline.into();
+    text_style.alignment = alignment.into();
+    text_style.line_height = line_height.into();
+
+    let origin = Point::new(position.x as i32, position.y as i32);
+    let text = Text::with_text_style(text, origin, font_style, text_style);
+    let line_pixels = line_height.to_pixels(font.character_size.height);
+    let fill_lines = background_color.is_some() && line_pixels > font.character_size.height;
+
+    match scale {
+        0 | 1 => {
+            if fill_lines { fill_line_backgrounds(target, &text, line_pixels)?; }
+            text.draw(target).map(|_| ())
+        }, scale => {
+            let mut target = ScaledTarget { target, origin, scale };
+            if fill_lines { fill_line_backgrounds(&mut target, &text, line_pixels)?; }
+            text.draw(&mut target).map(|_| ())
+        }
+    }
+}
+
+/// Fills every line of the text with its background color over the full line height. Embedded-graphics only
+/// fills the glyph boxes, which would leave gaps between the lines with line heights larger than the font.
+fn fill_line_backgrounds<T: DrawTarget<Color = Rgb888>>(
+    target: &mut T, text: &Text<MonoTextStyle<Rgb888>>, line_pixels: u32
+) -> Result<(), T::Error> {
+    let Some(background_color) = text.character_style.background_color else { return Ok(()); };
+
+    for (index, line) in text.text.split('\n').enumerate() {
+        let line_position = text.position + Point::new(0, index as i32 * line_pixels as i32);
+        let mut cell = Text::with_text_style(line, line_position, text.character_style, text.text_style).bounding_box();
+        cell.size.height = line_pixels;
+        target.fill_solid(&cell, background_color)?;
+    }
+
+    Ok(())
+}
+
 fn get_bounds(info: FrameBufferInfo) -> Rectangle {
     Rectangle::new(
         Point::new(0, 0),
@@ -296,24 +597,164 @@ fn get_bounds(info: FrameBufferInfo) -> Rectangle {
     )
 }
 
-fn set_pixel_in_at(frame_buffer: &mut [u8], frame_buffer_info: FrameBufferInfo, index: usize, color: Color) {
-    let pixel_buffer = &mut frame_buffer[index..index + frame_buffer_info.bytes_per_pixel];
+/// Returns the size of a single frame in bytes.
+fn frame_size(info: FrameBufferInfo) -> usize {
+    info.stride * info.height * info.bytes_per_pixel
+}
+
+/// How many frame buffer bytes go into one base64 line of a screenshot, giving 76 characters per line.
+const SCREENSHOT_LINE_BYTES: usize = 57;
+
+/// Streams the front buffer of the display over the serial port, so the host can turn it into an image.
+/// The dump starts with a `SCREENSHOT BEGIN` line describing the layout, followed by the raw frame buffer bytes
+/// in base64 lines, and ends with `SCREENSHOT END`. The serial port is only locked for one line at a time.
+pub fn send_screenshot(display: &dyn DisplayApi) {
+    let info = display.get_info();
+    let bytes = display.snapshot();
+
+    crate::with_serial_port(|serial_logger| writeln!(serial_logger,
+        "SCREENSHOT BEGIN width={} height={} stride={} bytes_per_pixel={} format={:?} length={}",
+        info.width, info.height, info.stride, info.bytes_per_pixel, info.pixel_format, bytes.len()
+    ));
+    base64::encode_chunked(bytes, SCREENSHOT_LINE_BYTES, |line| {
+        crate::with_serial_port(|serial_logger| writeln!(serial_logger, "{}", line));
+    });
+    crate::with_serial_port(|serial_logger| writeln!(serial_logger, "SCREENSHOT END"));
+}
+
+/// Returns whether a frame buffer of the given length can hold a frame of the given layout.
+/// A frame buffer that is empty or shorter than `height * stride * bytes_per_pixel` cannot be drawn to.
+pub fn is_frame_buffer_consistent(frame_buffer_len: usize, info: FrameBufferInfo) -> bool {
+    let frame_size = frame_size(info);
+    frame_size > 0 && frame_buffer_len >= frame_size
+}
+
+/// Compares the frame buffer with the back buffer it was presented from, pixel by pixel in the frame buffer's format.
+/// Returns the first pixel that differs and how many differ, or `None` if they match.
+/// A mismatch right after a swap means something else wrote to the frame buffer.
+#[allow(dead_code)]
+pub fn find_frame_buffer_mismatch(frame_buffer: &[u8], info: FrameBufferInfo, back_buffer: &[Color]) -> Option<(Position, usize)> {
+    let mut first = None;
+    let mut mismatches = 0;
+
+    for y in 0..info.height {
+        for x in 0..info.width {
+            let byte_offset = (y * info.stride + x) * info.bytes_per_pixel;
+            let expected = encode_pixel(info, back_buffer[y * info.width + x]);
+            if frame_buffer[byte_offset..byte_offset + info.bytes_per_pixel] != expected[..info.bytes_per_pixel] {
+                first.get_or_insert(Position::new(x, y));
+                mismatches += 1;
+            }
+        }
+    }
+
+    first.map(|position| (position, mismatches))
+}
+
+/// Returns the byte offset of a second page if the frame buffer is large enough to hold two frames.
+fn second_page_offset(frame_buffer_len: usize, info: FrameBufferInfo) -> Option<usize> {
+    let frame_size = frame_size(info);
+    if frame_size > 0 && frame_buffer_len >= 2 * frame_size { Some(frame_size) } else { None }
+}
+
+/// Returns an error if the given position lies outside the frame buffer.
+fn check_bounds(info: FrameBufferInfo, position: Position) -> Result<(), OutOfBounds> {
+    if position.x < info.width && position.y < info.height { Ok(()) } else {
+        Err(OutOfBounds { position, size: Size::new(info.width, info.height) })
+    }
+}
+
+/// Returns the part of an image at the given position that lies within the frame buffer.
+fn clip_size(info: FrameBufferInfo, position: Position, size: Size) -> Size {
+    Size::new(
+        size.width.min(info.width.saturating_sub(position.x)),
+        size.height.min(info.height.saturating_sub(position.y))
+    )
+}
+
+/// Returns the color that results from drawing a color over an existing pixel, blending if it is translucent.
+#[inline]
+fn blend_pixel(color: Color, existing: Color) -> Color {
+    if color.alpha == 255 { color } else { color.blend_over(existing) }
+}
+
+/// The byte order of pixels that span multiple bytes, like RGB565. PC frame buffers are little-endian.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// The low byte comes first.
+    LittleEndian,
+    /// The high byte comes first.
+    BigEndian
+}
+
+/// The byte order 16-bit pixels are written to and read from the frame buffer in.
+const FRAME_BUFFER_BYTE_ORDER: ByteOrder = ByteOrder::LittleEndian;
+
+/// Returns whether the frame buffer uses 16-bit pixels with 5 bits red, 6 bits green and 5 bits blue, from high to low.
+fn is_rgb565(frame_buffer_info: FrameBufferInfo) -> bool {
+    frame_buffer_info.bytes_per_pixel == 2 && matches!(frame_buffer_info.pixel_format,
+        PixelFormat::Unknown { red_position: 11, green_position: 5, blue_position: 0 }
+    )
+}
+
+/// Packs a color into the two bytes of an RGB565 pixel in the given byte order.
+pub fn pack_rgb565(color: Color, byte_order: ByteOrder) -> [u8; 2] {
+    let value = ((color.red as u16 >> 3) << 11) | ((color.green as u16 >> 2) << 5) | (color.blue as u16 >> 3);
+    match byte_order {
+        ByteOrder::LittleEndian => value.to_le_bytes(),
+        ByteOrder::BigEndian => value.to_be_bytes()
+    }
+}
+
+/// Unpacks the two bytes of an RGB565 pixel in the given byte order, scaling each channel back to 8 bits.
+pub fn unpack_rgb565(bytes: [u8; 2], byte_order: ByteOrder) -> Color {
+    let value = match byte_order {
+        ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+        ByteOrder::BigEndian => u16::from_be_bytes(bytes)
+    };
+    let (red, green, blue) = ((value >> 11) as u8 & 0x1F, (value >> 5) as u8 & 0x3F, value as u8 & 0x1F);
+    Color::new((red << 3) | (red >> 2), (green << 2) | (green >> 4), (blue << 3) | (blue >> 2))
+}
+
+/// Reads the pixel at the given byte offset of a buffer in the pixel format of the frame buffer.
+fn get_pixel_in_at(frame_buffer: &[u8], frame_buffer_info: FrameBufferInfo, index: usize) -> Color {
+    let pixel_buffer = &frame_buffer[index..index + frame_buffer_info.bytes_per_pixel];
+
+    if is_rgb565(frame_buffer_info) {
+        return unpack_rgb565([pixel_buffer[0], pixel_buffer[1]], FRAME_BUFFER_BYTE_ORDER);
+    }
 
     match frame_buffer_info.pixel_format {
-        PixelFormat::Rgb => {
-            pixel_buffer[0] = color.red;
-            pixel_buffer[1] = color.green;
-            pixel_buffer[2] = color.blue;
-        },
-        PixelFormat::Bgr => {
-            pixel_buffer[0] = color.blue;
-            pixel_buffer[1] = color.green;
-            pixel_buffer[2] = color.red;
-        },
-        PixelFormat::U8 => {
-            let gray = color.red / 3 + color.green / 3 + color.blue / 3;
-            pixel_buffer[0] = gray;
-        },
+        PixelFormat::Rgb => Color::new(pixel_buffer[0], pixel_buffer[1], pixel_buffer[2]),
+        PixelFormat::Bgr => Color::new(pixel_buffer[2], pixel_buffer[1], pixel_buffer[0]),
+        PixelFormat::U8 => Color::new(pixel_buffer[0], pixel_buffer[0], pixel_buffer[0]),
         other => panic!("Unsupported pixel format: {:?}", other)
     }
+}
+
+/// Returns the bytes of a pixel of the given color in the pixel format of the frame buffer.
+/// Only the first `bytes_per_pixel` bytes are meaningful.
+fn encode_pixel(frame_buffer_info: FrameBufferInfo, color: Color) -> [u8; 4] {
+    if is_rgb565(frame_buffer_info) {
+        let [first, second] = pack_rgb565(color, FRAME_BUFFER_BYTE_ORDER);
+        return [first, second, 0, 0];
+    }
+
+    match frame_buffer_info.pixel_format {
+        PixelFormat::Rgb => [color.red, color.green, color.blue, 0],
+        PixelFormat::Bgr => [color.blue, color.green, color.red, 0],
+        PixelFormat::U8 => [color.luminance(), 0, 0, 0],
+        other => panic!("Unsupported pixel format: {:?}", other)
+    }
+}
+
+fn set_pixel_in_at(frame_buffer: &mut [u8], frame_buffer_info: FrameBufferInfo, index: usize, color: Color) {
+    let color = if color.alpha == 255 { color } else {
+        color.blend_over(get_pixel_in_at(frame_buffer, frame_buffer_info, index))
+    };
+    let pixel = encode_pixel(frame_buffer_info, color);
+    let length = frame_buffer_info.bytes_per_pixel.min(pixel.len());
+
+    frame_buffer[index..index + length].copy_from_slice(&pixel[..length]);
 }
\ No newline at end of file
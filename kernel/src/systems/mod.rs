@@ -1 +1,11 @@
-pub mod display;
\ No newline at end of file
+pub mod display;
+pub mod shell;
+pub mod dialog;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "input-replay")]
+pub mod replay;
+#[cfg(feature = "remote-display")]
+pub mod remote;
+#[cfg(feature = "render-check")]
+pub mod render_check;
\ No newline at end of file
@@ -0,0 +1,97 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::api::display::{Color, Position, Region, Size};
+use crate::internal::base64;
+
+/// The longest run a single RLE entry can hold.
+const MAX_RUN: usize = u8::MAX as usize;
+/// The length of a frame header: x, y, width and height as little endian `u16` and the payload length as `u32`.
+const FRAME_HEADER_LEN: usize = 12;
+
+/// Run-length encodes pixels as entries of four bytes: the run length (1 to 255) followed by red, green and blue.
+pub fn encode_rle(pixels: impl IntoIterator<Item = Color>) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut run: Option<(Color, usize)> = None;
+
+    for pixel in pixels {
+        run = match run {
+            Some((color, length)) if length < MAX_RUN && same_rgb(color, pixel) => Some((color, length + 1)),
+            Some((color, length)) => {
+                push_run(&mut output, color, length);
+                Some((pixel, 1))
+            }, None => Some((pixel, 1))
+        };
+    }
+    if let Some((color, length)) = run { push_run(&mut output, color, length); }
+
+    output
+}
+
+/// Decodes pixels encoded by [`encode_rle`]. Returns `None` if the payload is not a whole number of entries.
+#[allow(dead_code)]
+pub fn decode_rle(payload: &[u8]) -> Option<Vec<Color>> {
+    let (entries, rest) = payload.as_chunks::<4>();
+    if !rest.is_empty() { return None; }
+
+    let mut pixels = Vec::new();
+    for entry in entries {
+        if entry[0] == 0 { return None; }
+        let color = Color::new(entry[1], entry[2], entry[3]);
+        pixels.extend(core::iter::repeat_n(color, entry[0] as usize));
+    }
+
+    Some(pixels)
+}
+
+/// Builds the frame for a region: a header with the region and the payload length, followed by the RLE payload.
+pub fn encode_region_frame(region: Region, pixels: impl IntoIterator<Item = Color>) -> Vec<u8> {
+    let payload = encode_rle(pixels);
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+
+    for value in [region.position.x, region.position.y, region.size.width, region.size.height] {
+        frame.extend_from_slice(&(value as u16).to_le_bytes());
+    }
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+
+    frame
+}
+
+/// Splits a frame built by [`encode_region_frame`] into its region and decoded pixels.
+/// Returns `None` if the frame is truncated or the payload does not cover the region.
+#[allow(dead_code)]
+pub fn decode_region_frame(frame: &[u8]) -> Option<(Region, Vec<Color>)> {
+    if frame.len() < FRAME_HEADER_LEN { return None; }
+
+    let field = |index: usize| u16::from_le_bytes([frame[index * 2], frame[index * 2 + 1]]) as usize;
+    let region = Region::new(Position::new(field(0), field(1)), Size::new(field(2), field(3)));
+    let payload_len = u32::from_le_bytes([frame[8], frame[9], frame[10], frame[11]]) as usize;
+
+    let pixels = decode_rle(frame.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + payload_len)?)?;
+    if pixels.len() != region.size.width * region.size.height { return None; }
+
+    Some((region, pixels))
+}
+
+/// Sends a region of the back buffer over the serial port as a `REGION` line holding its base64 encoded frame.
+/// `width` is the number of pixels per row of the back buffer.
+pub fn send_region(region: Region, back_buffer: &[Color], width: usize) {
+    let pixels = (region.position.y..region.position.y + region.size.height).flat_map(|y| {
+        let start = y * width + region.position.x;
+        back_buffer[start..start + region.size.width].iter().copied()
+    });
+
+    let mut line = String::from("REGION ");
+    base64::encode_into(&encode_region_frame(region, pixels), &mut line);
+    crate::with_serial_port(|serial_logger| writeln!(serial_logger, "{}", line));
+}
+
+fn push_run(output: &mut Vec<u8>, color: Color, length: usize) {
+    output.extend_from_slice(&[length as u8, color.red, color.green, color.blue]);
+}
+
+/// Compares only the color channels, as the frame buffer has no alpha.
+fn same_rgb(a: Color, b: Color) -> bool {
+    a.red == b.red && a.green == b.green && a.blue == b.blue
+}
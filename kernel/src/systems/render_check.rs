@@ -0,0 +1,156 @@
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use bootloader_api::info::FrameBufferInfo;
+use embedded_graphics::mono_font::MonoFont;
+use crate::api::display::{Color, DisplayApi, Fonts, Palette, Position, Size, TextAlignment, TextBaseline, TextLineHeight};
+use crate::drivers::display::{CommonDisplayDriver, DisplayDriverManager, DisplayDriverType};
+use crate::drivers::display::text::{TextColor, TextDisplayDriver, TextDisplayDriverArgs};
+
+/// A single call made to a [`RecordingDisplay`].
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayCall {
+    Draw { length: usize },
+    DrawChar {
+        character: char, position: Position,
+        text_color: Color, background_color: Option<Color>,
+        underline: bool, strikethrough: bool
+    },
+    DrawText {
+        text: String, position: Position,
+        text_color: Color, background_color: Option<Color>,
+        underline: bool, strikethrough: bool
+    },
+    DrawImage { position: Position, size: Size },
+    DrawIndexed { position: Position, size: Size },
+    SetPalette,
+    Clear { color: Color },
+    Swap,
+    SwapAll
+}
+
+/// A display that does not draw anything but records every call made to it,
+/// so it can be checked exactly what a driver tried to render.
+pub struct RecordingDisplay {
+    calls: Vec<DisplayCall>,
+    frame_buffer_info: FrameBufferInfo
+} #[allow(dead_code)] impl RecordingDisplay {
+    pub fn new(frame_buffer_info: FrameBufferInfo) -> Self {
+        Self { calls: Vec::new(), frame_buffer_info }
+    }
+
+    /// Returns all calls recorded so far.
+    pub fn get_calls(&self) -> &[DisplayCall] {
+        &self.calls
+    }
+
+    /// Returns all calls recorded so far and clears the recording.
+    pub fn take_calls(&mut self) -> Vec<DisplayCall> {
+        core::mem::take(&mut self.calls)
+    }
+} impl DisplayApi for RecordingDisplay {
+    fn draw(&mut self, buffer: &[u8]) {
+        self.calls.push(DisplayCall::Draw { length: buffer.len() });
+    }
+
+    fn draw_char(
+        &mut self, character: char, position: Position,
+        text_color: Color, background_color: Option<Color>,
+        _font: MonoFont, underline: bool, strikethrough: bool,
+        _baseline: TextBaseline, _alignment: TextAlignment, _line_height: TextLineHeight, _scale: u32
+    ) {
+        self.calls.push(DisplayCall::DrawChar {
+            character, position,
+            text_color, background_color,
+            underline, strikethrough
+        });
+    }
+
+    fn draw_text(
+        &mut self, text: &str, position: Position,
+        text_color: Color, background_color: Option<Color>,
+        _font: MonoFont, underline: bool, strikethrough: bool,
+        _baseline: TextBaseline, _alignment: TextAlignment, _line_height: TextLineHeight, _scale: u32
+    ) {
+        self.calls.push(DisplayCall::DrawText {
+            text: String::from(text), position,
+            text_color, background_color,
+            underline, strikethrough
+        });
+    }
+
+    fn draw_image(&mut self, position: Position, size: Size, _pixels: &[Color]) {
+        self.calls.push(DisplayCall::DrawImage { position, size });
+    }
+
+    fn draw_indexed(&mut self, position: Position, size: Size, _indices: &[u8]) {
+        self.calls.push(DisplayCall::DrawIndexed { position, size });
+    }
+
+    fn set_palette(&mut self, _palette: Palette) {
+        self.calls.push(DisplayCall::SetPalette);
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.calls.push(DisplayCall::Clear { color });
+    }
+
+    fn swap(&mut self) {
+        self.calls.push(DisplayCall::Swap);
+    }
+
+    fn swap_all(&mut self) {
+        self.calls.push(DisplayCall::SwapAll);
+    }
+
+    fn take_redraw_request(&mut self) -> bool { false }
+
+    fn get_info(&self) -> FrameBufferInfo { self.frame_buffer_info }
+
+    fn snapshot(&self) -> &[u8] { &[] }
+}
+
+/// Renders a row with two text colors through the text driver into a [`RecordingDisplay`] and checks that
+/// the driver merged it into exactly one `draw_text` call per color run. Returns whether the check passed.
+pub fn run(frame_buffer_info: FrameBufferInfo) -> bool {
+    let display = Rc::new(RefCell::new(RecordingDisplay::new(frame_buffer_info)));
+    let mut driver_manager = DisplayDriverManager::new();
+    driver_manager.set_driver(DisplayDriverType::Text(
+        TextDisplayDriver::new(),
+        TextDisplayDriverArgs::new(Rc::new(RefCell::new(Fonts::default())))
+    ), display.clone());
+
+    // The first frame draws the whole cleared buffer, only the frame after writing is of interest.
+    driver_manager.draw_all();
+    display.borrow_mut().take_calls();
+
+    if let DisplayDriverType::Text(driver, _) = &mut driver_manager.current_driver {
+        driver.set_text_color(TextColor::Red);
+        driver.write_string("AB");
+        driver.set_text_color(TextColor::Blue);
+        driver.write_string("CD");
+    }
+    driver_manager.draw_all();
+
+    let texts = display.borrow().get_calls().iter().filter_map(|call| match call {
+        DisplayCall::DrawText { text, position, text_color, .. } => Some((text.clone(), *position, *text_color)),
+        _ => None
+    }).collect::<Vec<_>>();
+    let expected: [(&str, Color); 2] = [("AB", TextColor::Red.into()), ("CD", TextColor::Blue.into())];
+
+    let passed = texts.len() == expected.len()
+        && texts.iter().zip(expected.iter()).all(|((text, _, color), (expected_text, expected_color))| {
+            text == expected_text && color == expected_color
+        })
+        && texts[0].1.y == texts[1].1.y && texts[0].1.x < texts[1].1.x;
+
+    if passed {
+        crate::kinfo!("Render check passed: a two color row was drawn as two draw_text calls.");
+    } else {
+        crate::kerror!("Render check failed: expected {:?}, recorded {:?}.", expected, texts);
+    }
+
+    passed
+}
\ No newline at end of file
@@ -0,0 +1,69 @@
+use alloc::vec::Vec;
+use crate::drivers::display::text::TextDisplayDriver;
+use crate::internal::serial;
+
+/// The script replayed after boot when the `input-replay` feature is enabled.
+pub const BOOT_SCRIPT: &str = "help\n";
+/// A line the shell has to have written once the boot script was replayed.
+pub const BOOT_SCRIPT_EXPECTED: &str = "Available commands:";
+/// How many kernel ticks to wait before feeding each byte of the boot script.
+pub const BOOT_SCRIPT_DELAY_TICKS: u64 = 10;
+
+/// A single scripted input byte and how many ticks to wait before feeding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayEvent {
+    pub byte: u8,
+    pub delay_ticks: u64
+}
+
+/// Feeds a predefined script of input bytes into the serial input queue, so the shell
+/// receives them exactly as if they were typed over the serial port.
+pub struct InputReplay {
+    events: Vec<ReplayEvent>,
+    next: usize,
+    waited_ticks: u64,
+    finish_reported: bool
+} #[allow(dead_code)] impl InputReplay {
+    pub fn new(events: Vec<ReplayEvent>) -> Self {
+        Self { events, next: 0, waited_ticks: 0, finish_reported: false }
+    }
+
+    /// Creates a replay of the bytes of the given text, waiting the same number of ticks before each one.
+    pub fn from_text(text: &str, delay_ticks: u64) -> Self {
+        Self::new(text.bytes()
+            .map(|byte| ReplayEvent { byte, delay_ticks })
+            .collect())
+    }
+
+    /// Feeds every event that is due into the serial input queue. Meant to be called once per kernel tick.
+    pub fn tick(&mut self) {
+        while let Some(event) = self.events.get(self.next) {
+            if self.waited_ticks < event.delay_ticks {
+                self.waited_ticks += 1;
+                return;
+            }
+
+            serial::push_input_byte(event.byte);
+            self.next += 1;
+            self.waited_ticks = 0;
+        }
+    }
+
+    /// Returns whether all events have been fed.
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    /// Returns true exactly once, on the first call after all events have been fed.
+    pub fn take_finished(&mut self) -> bool {
+        if !self.is_finished() || self.finish_reported { return false; }
+
+        self.finish_reported = true;
+        true
+    }
+}
+
+/// Returns whether any visible row of the text display contains the given text.
+pub fn screen_contains(driver: &TextDisplayDriver, text: &str) -> bool {
+    (0..driver.get_viewport().size.height).any(|row| driver.read_row(row).contains(text))
+}
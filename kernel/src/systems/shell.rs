@@ -0,0 +1,178 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::api::display::Position;
+use crate::drivers::display::text::TextDisplayDriver;
+use crate::internal::{allocator, idt, power};
+
+pub type CommandHandler = fn(&Shell, &[&str], &mut TextDisplayDriver<'_>);
+
+pub struct Shell {
+    prompt: String,
+    line: String,
+    commands: Vec<(&'static str, CommandHandler)>,
+    exited: bool
+} #[allow(dead_code)] impl Shell {
+    /// Creates a new shell with the built-in commands already registered.
+    pub fn new(prompt: &str) -> Self {
+        let mut shell = Self {
+            prompt: String::from(prompt),
+            line: String::new(),
+            commands: Vec::new(),
+            exited: false
+        };
+
+        shell.register("help", help_command);
+        shell.register("clear", clear_command);
+        shell.register("echo", echo_command);
+        shell.register("meminfo", meminfo_command);
+        shell.register("irqstats", irqstats_command);
+        shell.register("screenshot", screenshot_command);
+        shell.register("reboot", reboot_command);
+        shell.register("shutdown", shutdown_command);
+
+        shell
+    }
+
+    /// Registers a command. A command that is already registered under the same name gets replaced.
+    pub fn register(&mut self, name: &'static str, handler: CommandHandler) {
+        if let Some(command) = self.commands.iter_mut().find(|(command_name, _)| *command_name == name) {
+            command.1 = handler;
+        } else { self.commands.push((name, handler)); }
+    }
+
+    /// Returns the names of all registered commands.
+    pub fn get_commands(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.commands.iter().map(|(name, _)| *name)
+    }
+
+    /// Writes the prompt to the console.
+    pub fn start(&mut self, driver: &mut TextDisplayDriver) {
+        driver.write_string(&self.prompt);
+    }
+
+    /// Returns whether the shell was exited with Ctrl+D and no longer handles input.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Handles a single character typed into the console.
+    ///
+    /// Control combinations arrive as their control characters: Ctrl+C discards the line and starts a new prompt,
+    /// Ctrl+L clears the screen while keeping the line, Ctrl+U erases the line and Ctrl+D on an empty line exits the shell.
+    pub fn handle_char(&mut self, character: char, driver: &mut TextDisplayDriver) {
+        if self.exited { return; }
+
+        match character {
+            '\n' => {
+                driver.new_line();
+                let line = core::mem::take(&mut self.line);
+                self.execute(&line, driver);
+                driver.write_string(&self.prompt);
+            }, '\x08' | '\x7F' => {
+                if self.line.pop().is_some() { erase_last_char(driver); }
+            }, '\x03' => {
+                driver.write_line("^C");
+                self.line.clear();
+                driver.write_string(&self.prompt);
+            }, '\x0C' => {
+                driver.clear_buffer();
+                driver.init_redraw();
+                driver.write_string(&self.prompt);
+                driver.write_string(&self.line);
+            }, '\x15' => {
+                while self.line.pop().is_some() { erase_last_char(driver); }
+            }, '\x04' => {
+                if self.line.is_empty() {
+                    driver.write_line("exit");
+                    self.exited = true;
+                }
+            }, character if character.is_control() => {},
+            character => {
+                self.line.push(character);
+                driver.write_char(character);
+            }
+        }
+    }
+
+    /// Executes a command line by dispatching its first word to the matching command.
+    pub fn execute(&self, line: &str, driver: &mut TextDisplayDriver) {
+        let tokens = tokenize(line);
+
+        if let Some((name, args)) = tokens.split_first() {
+            match self.commands.iter().find(|(command_name, _)| command_name == name) {
+                Some((_, handler)) => handler(self, args, driver),
+                None => driver.write_line(&format!("Unknown command: {}", name))
+            }
+        }
+    }
+}
+
+/// Splits a command line into its whitespace separated words.
+pub fn tokenize(line: &str) -> Vec<&str> {
+    line.split_whitespace().collect()
+}
+
+fn erase_last_char(driver: &mut TextDisplayDriver) {
+    let cursor = driver.get_cursor_position();
+    let position = if cursor.x > 0 {
+        Position::new(cursor.x - 1, cursor.y)
+    } else if cursor.y > 0 {
+        Position::new(driver.get_viewport().size.width - 1, cursor.y - 1)
+    } else { return; };
+
+    driver.clear_cell(position.y, position.x);
+    driver.move_cursor(position);
+}
+
+fn help_command(shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    driver.write_line("Available commands:");
+    for name in shell.get_commands() {
+        driver.write_line(&format!("  {}", name));
+    }
+}
+
+fn clear_command(_shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    driver.clear_buffer();
+    driver.init_redraw();
+}
+
+fn echo_command(_shell: &Shell, args: &[&str], driver: &mut TextDisplayDriver) {
+    driver.write_line(&args.join(" "));
+}
+
+fn meminfo_command(_shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    let stats = allocator::heap_stats();
+    driver.write_line(&format!("Heap: {} bytes used, {} bytes free, {} bytes total",
+        stats.used, stats.free, stats.size
+    ));
+}
+
+fn irqstats_command(_shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    let stats = idt::interrupt_stats();
+    driver.write_line(&format!("Timer: {}", stats.timer));
+    driver.write_line(&format!("Serial: {}", stats.serial));
+    driver.write_line(&format!("Breakpoint: {}", stats.breakpoint));
+    driver.write_line(&format!("Invalid opcode: {}", stats.invalid_opcode));
+    driver.write_line(&format!("General protection fault: {}", stats.general_protection_fault));
+    driver.write_line(&format!("Page fault: {}", stats.page_fault));
+    driver.write_line(&format!("Double fault: {}", stats.double_fault));
+    driver.write_line(&format!("Spurious: {}", stats.spurious));
+}
+
+fn screenshot_command(_shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    if driver.send_screenshot() {
+        driver.write_line("Sent screenshot over serial.");
+    } else { driver.write_line("No display to take a screenshot of."); }
+}
+
+fn reboot_command(_shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    driver.write_line("Rebooting...");
+    power::reboot();
+}
+
+fn shutdown_command(_shell: &Shell, _args: &[&str], driver: &mut TextDisplayDriver) {
+    driver.write_line("Shutting down...");
+    power::shutdown();
+}
@@ -14,6 +14,7 @@ fn main() {
     qemu.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
 
     qemu.arg("-serial").arg("stdio");
+    qemu.arg("-device").arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
 
     let accel_enabled = env::var("ACCEL_ENABLED").unwrap_or("true".to_string())
         .parse::<bool>().unwrap();
@@ -32,5 +33,11 @@ fn main() {
     qemu.arg("-cpu").arg("qemu64");
 
     let exit_status = qemu.status().unwrap();
-    process::exit(exit_status.code().unwrap_or(-1));
+    process::exit(match exit_status.code() {
+        // The kernel wrote QemuExitCode::Success or QemuExitCode::Failed to the isa-debug-exit device.
+        Some(33) => 0,
+        Some(35) => 1,
+        Some(code) => code,
+        None => -1
+    });
 }
\ No newline at end of file